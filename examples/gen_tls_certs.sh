@@ -0,0 +1,10 @@
+#!/bin/bash
+# Generates the throwaway CA and client certificate the TLS tests read from
+# examples/tls. Run it from the repository root.
+set -euo pipefail
+
+mkdir -p examples/tls
+cd examples/tls
+openssl req -x509 -newkey rsa:2048 -nodes -days 1 -subj "/CN=casbin-rs test CA" -keyout ca.key -out ca.crt
+openssl req -newkey rsa:2048 -nodes -subj "/CN=casbin_rs" -keyout client.key -out client.csr
+openssl x509 -req -days 1 -set_serial 1 -in client.csr -CA ca.crt -CAkey ca.key -out client.crt
@@ -1,586 +1,1440 @@
 #![allow(clippy::suspicious_else_formatting)]
 #![allow(clippy::toplevel_ref_arg)]
-use crate::models::{CasbinRule, NewCasbinRule};
+use crate::builder::{FilterMatch, OnConflict, OnMissing};
+use crate::changelog::PolicyOperation;
+use crate::filter::{FieldMatch, PolicyFilter, PtypeMatch};
+use crate::models::{CasbinRule, JsonbRule, NewCasbinRule};
+use crate::table::{StorageLayout, Table};
 use crate::Error;
-use casbin::{error::AdapterError, Error as CasbinError, Filter, Result};
-use deadpool_postgres::Pool;
-use futures::try_join;
-use tokio_postgres::{types::Type, SimpleQueryMessage};
+use casbin::{
+    error::{AdapterError, PolicyError},
+    Error as CasbinError, Result,
+};
+use deadpool_postgres::{Client, Pool, Transaction};
+use futures::{pin_mut, try_join, TryStreamExt};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio_postgres::{
+    binary_copy::{BinaryCopyInWriter, BinaryCopyOutStream},
+    types::{ToSql, Type},
+    Row, Statement,
+};
 
 pub type ConnectionPool = Pool;
 
-pub async fn new(conn: &ConnectionPool) -> Result<Vec<SimpleQueryMessage>> {
+pub async fn remove_policy(
+    conn: &ConnectionPool,
+    table: &Table,
+    pt: &str,
+    rule: Vec<String>,
+) -> Result<bool> {
+    let rule = normalize_casbin_rule(table, pt, rule)?;
     let client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
+    let mut types = table.rule_types();
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "DELETE FROM {} WHERE
+                    ptype = $1 AND
+                    {}",
+            table,
+            table.rule_match(2)
+        ),
+        Logged::Row(PolicyOperation::Remove),
+        Some("remove_policy"),
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
+    let mut params = rule_params(table, &pt, &rule);
+    params.extend(channel);
     client
-        .simple_query(
-            "CREATE TABLE IF NOT EXISTS casbin_rule (
-                    id SERIAL PRIMARY KEY,
-                    ptype VARCHAR NOT NULL,
-                    v0 VARCHAR NOT NULL,
-                    v1 VARCHAR NOT NULL,
-                    v2 VARCHAR NOT NULL,
-                    v3 VARCHAR NOT NULL,
-                    v4 VARCHAR NOT NULL,
-                    v5 VARCHAR NOT NULL,
-                    CONSTRAINT unique_key_pg_adapter UNIQUE(ptype, v0, v1, v2, v3, v4, v5)
-                    )
-        ",
-        )
+        .query_opt(&stmt, &params)
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
+        .map(|s| s.is_some())
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))
 }
 
-pub async fn remove_policy(conn: &ConnectionPool, pt: &str, rule: Vec<String>) -> Result<bool> {
-    let rule = normalize_casbin_rule(rule);
+/// Removes `rules` in one transaction and returns, for each of them, whether
+/// it was removed. With [`OnMissing::Fail`] a rule that is not stored rolls
+/// the whole batch back and fails it with [`Error::RuleMissing`], which
+/// names the first such rule as it was passed.
+pub async fn remove_policies(
+    conn: &ConnectionPool,
+    table: &Table,
+    pt: &str,
+    rules: Vec<Vec<String>>,
+    on_missing: OnMissing,
+) -> Result<Vec<bool>> {
+    let values = rules
+        .iter()
+        .map(|rule| normalize_casbin_rule(table, pt, rule.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let mut client = conn
+        .get()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    let new_rules = values
+        .iter()
+        .map(|values| NewCasbinRule {
+            ptype: pt,
+            values: values.iter().map(String::as_str).collect(),
+        })
+        .collect::<Vec<_>>();
+    let arrays = RuleArrays::new(table, &new_rules);
+    let mut types = arrays.types();
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "DELETE FROM {} WHERE ( ptype, {} ) IN (
+                    {}
+                 )",
+            table,
+            table.rule_columns(),
+            arrays.select(table)
+        ),
+        Logged::Row(PolicyOperation::Remove),
+        None,
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let mut rows = vec![];
+    for chunk in new_rules.chunks(CHUNK_SIZE) {
+        let values = arrays.bind(chunk);
+        let mut params = values
+            .iter()
+            .map(|array| array as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        params.extend(channel);
+        rows.extend(
+            transaction
+                .query(&stmt, &params)
+                .await
+                .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?,
+        );
+    }
+    let removed = changed_rules(&new_rules, &rows);
+    if on_missing == OnMissing::Fail {
+        if let Some(missing) = removed.iter().position(|removed| !removed) {
+            return Err(CasbinError::from(AdapterError(Box::new(
+                Error::RuleMissing {
+                    ptype: pt.to_owned(),
+                    rule: rules[missing].clone(),
+                },
+            ))));
+        }
+    }
+    if removed.contains(&true) {
+        notify(&transaction, table, "remove_policies", None).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok(removed)
+}
+
+/// Removes every rule whose values from `field_index` on match
+/// `field_values`, `field_values[0]` being compared with the value at
+/// `field_index`. Empty strings match anything. Returns the number of rules
+/// removed.
+pub async fn remove_filtered_policy(
+    conn: &ConnectionPool,
+    table: &Table,
+    pt: &str,
+    field_index: usize,
+    field_values: Vec<String>,
+) -> Result<u64> {
+    if let Some(max) = table.max_fields() {
+        if field_index + field_values.len() > max {
+            return Err(rule_too_long(table, pt, field_index + field_values.len()));
+        }
+    }
     let client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    let stmt = client
-        .prepare_typed(
-            "DELETE FROM casbin_rule WHERE
+    let (predicate, filter_params) = filter_predicate(table, field_index, &field_values, 2);
+    let mut types = vec![Type::TEXT; filter_params.len() + 1];
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "DELETE FROM {} WHERE
                     ptype = $1 AND
-                    v0 = $2 AND
-                    v1 = $3 AND
-                    v2 = $4 AND
-                    v3 = $5 AND
-                    v4 = $6 AND
-                    v5 = $7
-              RETURNING id",
-            &[
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-            ],
-        )
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+                    {}",
+            table, predicate
+        ),
+        Logged::Row(PolicyOperation::Remove),
+        Some("remove_filtered_policy"),
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
+
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&pt];
+    params.extend(filter_params);
+    params.extend(channel);
     client
-        .query_opt(
-            &stmt,
-            &[
-                &pt, &rule[0], &rule[1], &rule[2], &rule[3], &rule[4], &rule[5],
-            ],
-        )
+        .query(&stmt, &params)
         .await
-        .map(|s| s.is_some())
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
+        .map(|rows| rows.len() as u64)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))
 }
 
-pub async fn remove_policies(
+pub(crate) async fn update_policy(
     conn: &ConnectionPool,
+    table: &Table,
     pt: &str,
-    rules: Vec<Vec<String>>,
+    old_rule: Vec<String>,
+    new_rule: Vec<String>,
+) -> Result<bool> {
+    update_policies(
+        conn,
+        table,
+        pt,
+        vec![old_rule],
+        vec![new_rule],
+        "update_policy",
+    )
+    .await
+}
+
+/// Replaces each of `old_rules` with the rule at the same position in
+/// `new_rules`, all or nothing: `false`, with nothing changed, unless every
+/// old rule was found.
+pub(crate) async fn update_policies(
+    conn: &ConnectionPool,
+    table: &Table,
+    pt: &str,
+    old_rules: Vec<Vec<String>>,
+    new_rules: Vec<Vec<String>>,
+    op: &'static str,
 ) -> Result<bool> {
+    if old_rules.len() != new_rules.len() {
+        return Err(CasbinError::from(PolicyError::UnmatchPolicyDefinition(
+            old_rules.len(),
+            new_rules.len(),
+        )));
+    }
+    let old_rules = old_rules
+        .into_iter()
+        .map(|rule| normalize_casbin_rule(table, pt, rule))
+        .collect::<Result<Vec<_>>>()?;
+    let new_rules = new_rules
+        .into_iter()
+        .map(|rule| normalize_casbin_rule(table, pt, rule))
+        .collect::<Result<Vec<_>>>()?;
     let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    // $1 is the ptype, then the new rule, then the old one.
+    let mut types = table.rule_types();
+    types.extend_from_slice(&table.rule_types()[1..]);
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "UPDATE {0} SET {1}
+               FROM (
+                 SELECT id AS prev_id, {2} AS prev_rule FROM {0}
+                  WHERE ptype = $1 AND
+                    {3}
+               ) AS prev
+              WHERE id = prev_id",
+            table,
+            table.rule_assignment(2),
+            table.rule_array(),
+            table.rule_match(table.rule_types().len() + 1)
+        ),
+        Logged::Update,
+        None,
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
 
-    let stmt = client
-        .prepare_typed(
-            "DELETE FROM casbin_rule WHERE
-                ptype = $1 AND
-                v0 = $2 AND
-                v1 = $3 AND
-                v2 = $4 AND
-                v3 = $5 AND
-                v4 = $6 AND
-                v5 = $7
-              RETURNING id",
-            &[
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-            ],
-        )
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
     let transaction = client
         .transaction()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    for rule in rules {
-        let rule = normalize_casbin_rule(rule);
-        transaction
-            .query_one(
-                &stmt,
-                &[
-                    &pt, &rule[0], &rule[1], &rule[2], &rule[3], &rule[4], &rule[5],
-                ],
-            )
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    for (old_rule, new_rule) in old_rules.iter().zip(&new_rules) {
+        let mut params = rule_params(table, &pt, new_rule);
+        params.extend(rule_params(table, &pt, old_rule).into_iter().skip(1));
+        params.extend(channel);
+        let rows = transaction
+            .query(&stmt, &params)
             .await
-            .map(|s| s.is_empty())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+        if rows.is_empty() {
+            return Ok(false);
+        }
+    }
+    if !old_rules.is_empty() {
+        notify(&transaction, table, op, None).await?;
     }
     transaction
         .commit()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    Ok(true)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok(!old_rules.is_empty())
 }
 
-pub async fn remove_filtered_policy(
+/// Replaces every rule matching `field_values` from `field_index` on, empty
+/// strings matching anything, with `new_rules`, in one transaction. Returns
+/// the rules replaced, like casbin's `UpdateFilteredPolicies`.
+pub(crate) async fn update_filtered_policies(
     conn: &ConnectionPool,
+    table: &Table,
     pt: &str,
     field_index: usize,
     field_values: Vec<String>,
-) -> Result<bool> {
-    let client = conn
+    new_rules: Vec<Vec<String>>,
+) -> Result<Vec<Vec<String>>> {
+    if let Some(max) = table.max_fields() {
+        if field_index + field_values.len() > max {
+            return Err(rule_too_long(table, pt, field_index + field_values.len()));
+        }
+    }
+    let new_rules = new_rules
+        .into_iter()
+        .map(|rule| normalize_casbin_rule(table, pt, rule))
+        .collect::<Result<Vec<_>>>()?;
+    let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    let field_values = normalize_casbin_rule(field_values);
-    if field_index == 5 {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
-                    ptype = $1 AND
-                    (v5 is NULL OR v5 = $2)
-                  RETURNING id",
-                &[Type::TEXT, Type::TEXT],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-        client
-            .query_opt(&stmt, &[&pt, &field_values[5]])
-            .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
-    } else if field_index == 4 {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
+    let (predicate, filter_params) = filter_predicate(table, field_index, &field_values, 2);
+    let mut delete_types = vec![Type::TEXT; filter_params.len() + 1];
+    let (sql_delete, delete_channel) = returning(
+        table,
+        format!(
+            "DELETE FROM {} WHERE
                     ptype = $1 AND
-                    (v4 is NULL OR v4 = $2) AND
-                    (v5 is NULL OR v5 = $3)
-                  RETURNING id",
-                &[Type::TEXT, Type::TEXT, Type::TEXT],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-        client
-            .query_opt(&stmt, &[&pt, &field_values[4], &field_values[5]])
-            .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
-    } else if field_index == 3 {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
-                    ptype = $1 AND
-                    (v3 is NULL OR v3 = $2) AND
-                    (v4 is NULL OR v4 = $3) AND
-                    (v5 is NULL OR v5 = $4)
-                  RETURNING id",
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-        client
-            .query_opt(
-                &stmt,
-                &[&pt, &field_values[3], &field_values[4], &field_values[5]],
-            )
-            .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
-    } else if field_index == 2 {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
-                    ptype = $1 AND
-                    (v2 is NULL OR v2 = $2) AND
-                    (v3 is NULL OR v3 = $3) AND
-                    (v4 is NULL OR v4 = $4) AND
-                    (v5 is NULL OR v5 = $5)
-                  RETURNING id",
-                &[Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-        client
-            .query_opt(
-                &stmt,
-                &[
-                    &pt,
-                    &field_values[2],
-                    &field_values[3],
-                    &field_values[4],
-                    &field_values[5],
-                ],
-            )
-            .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
-    } else if field_index == 1 {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
-                    ptype = $1 AND
-                    (v1 is NULL OR v1 = $2) AND
-                    (v2 is NULL OR v2 = $3) AND
-                    (v3 is NULL OR v3 = $4) AND
-                    (v4 is NULL OR v4 = $5) AND
-                    (v5 is NULL OR v5 = $6)
-                  RETURNING id",
-                &[
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                ],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-        client
-            .query_opt(
-                &stmt,
-                &[
-                    &pt,
-                    &field_values[1],
-                    &field_values[2],
-                    &field_values[3],
-                    &field_values[4],
-                    &field_values[5],
-                ],
-            )
-            .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
-    } else {
-        let stmt = client
-            .prepare_typed(
-                "DELETE FROM casbin_rule WHERE
-                    ptype = $1 AND
-                    (v0 is NULL OR v0 = $2) AND
-                    (v1 is NULL OR v1 = $3) AND
-                    (v2 is NULL OR v2 = $4) AND
-                    (v3 is NULL OR v3 = $5) AND
-                    (v4 is NULL OR v4 = $6) AND
-                    (v5 is NULL OR v5 = $7)
-                  RETURNING id",
-                &[
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                    Type::TEXT,
-                ],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+                    {}",
+            table, predicate
+        ),
+        Logged::Row(PolicyOperation::Remove),
+        None,
+        &mut delete_types,
+    );
+    let stmt_delete = prepare_cached(&client, table, &sql_delete, &delete_types).await?;
+    let mut delete_params: Vec<&(dyn ToSql + Sync)> = vec![&pt];
+    delete_params.extend(filter_params);
+    delete_params.extend(delete_channel);
 
-        client
-            .query_opt(
-                &stmt,
-                &[
-                    &pt,
-                    &field_values[0],
-                    &field_values[1],
-                    &field_values[2],
-                    &field_values[3],
-                    &field_values[4],
-                    &field_values[5],
-                ],
-            )
+    let rules = new_rules
+        .iter()
+        .map(|values| NewCasbinRule {
+            ptype: pt,
+            values: values.iter().map(String::as_str).collect(),
+        })
+        .collect::<Vec<_>>();
+    let arrays = RuleArrays::new(table, &rules);
+    let mut insert_types = arrays.types();
+    let (sql_insert, insert_channel) = returning(
+        table,
+        format!(
+            "INSERT INTO {} ( ptype, {} )
+                 {}",
+            table,
+            table.rule_columns(),
+            arrays.select(table)
+        ),
+        Logged::Row(PolicyOperation::Add),
+        None,
+        &mut insert_types,
+    );
+    let stmt_insert = prepare_cached(&client, table, &sql_insert, &insert_types).await?;
+
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let replaced = transaction
+        .query(&stmt_delete, &delete_params)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    for chunk in rules.chunks(CHUNK_SIZE) {
+        let values = arrays.bind(chunk);
+        let mut params = values
+            .iter()
+            .map(|array| array as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        params.extend(insert_channel);
+        transaction
+            .query(&stmt_insert, &params)
             .await
-            .map(|s| s.is_some())
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
     }
+    if !replaced.is_empty() || !rules.is_empty() {
+        notify(&transaction, table, "update_filtered_policies", None).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    // Column layouts return the padding as trailing empty values.
+    Ok(replaced
+        .iter()
+        .map(|row| {
+            let mut rule: Vec<String> = row.get("rule");
+            while rule.last().is_some_and(String::is_empty) {
+                rule.pop();
+            }
+            rule
+        })
+        .collect())
 }
 
-pub(crate) async fn load_policy(conn: &ConnectionPool) -> Result<Vec<CasbinRule>> {
+/// Hands every rule to `on_rule` as it arrives, through a binary `COPY` when
+/// Postgres estimates the table to hold `copy_threshold` rows or more.
+pub(crate) async fn load_policy(
+    conn: &ConnectionPool,
+    table: &Table,
+    copy_threshold: usize,
+    on_rule: &mut (dyn FnMut(CasbinRule) + Send),
+) -> Result<()> {
     let client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    let stmt = client
-        .prepare_typed("SELECT * FROM casbin_rule", &[])
+    // The default threshold never picks `COPY`, so there is nothing to ask.
+    let estimate: i64 = if copy_threshold == usize::MAX {
+        0
+    } else {
+        let stmt = prepare_cached(
+            &client,
+            table,
+            "SELECT reltuples::BIGINT FROM pg_class WHERE oid = to_regclass($1)",
+            &[Type::TEXT],
+        )
+        .await?;
+        client
+            .query_opt(&stmt, &[&table.to_string()])
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?
+            .map_or(0, |row| row.get(0))
+    };
+    if usize::try_from(estimate).is_ok_and(|estimate| estimate >= copy_threshold) {
+        load_policy_copy(&client, table, on_rule).await
+    } else {
+        let stmt = prepare_cached(&client, table, &rules_query(table, "TRUE"), &[]).await?;
+        query_rules(&client, &stmt, &Params::default(), on_rule).await
+    }
+}
+
+async fn load_policy_copy(
+    client: &Client,
+    table: &Table,
+    on_rule: &mut (dyn FnMut(CasbinRule) + Send),
+) -> Result<()> {
+    let stream = client
+        .copy_out(&format!(
+            "COPY ( SELECT {} FROM {} ) TO STDOUT BINARY",
+            table.select_columns(),
+            table
+        ))
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    let casbin_rule: Vec<CasbinRule> = client
-        .query(&stmt, &[])
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    // `Jsonb` rules are selected as text arrays.
+    let mut types = vec![Type::INT4, Type::VARCHAR];
+    match table.layout {
+        StorageLayout::Columns => types.extend(vec![Type::VARCHAR; table.fields]),
+        StorageLayout::TextArray | StorageLayout::Jsonb => types.push(Type::TEXT_ARRAY),
+    }
+    let rows = BinaryCopyOutStream::new(stream, &types);
+    pin_mut!(rows);
+
+    while let Some(row) = rows
+        .try_next()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?
-        .into_iter()
-        .map(CasbinRule::from)
-        .collect();
-    Ok(casbin_rule)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?
+    {
+        let values = match table.layout {
+            StorageLayout::Columns => (2..types.len()).map(|idx| row.get(idx)).collect(),
+            StorageLayout::TextArray | StorageLayout::Jsonb => row.get(2),
+        };
+        on_rule(CasbinRule {
+            id: row.get(0),
+            ptype: row.get(1),
+            values,
+        });
+    }
+    Ok(())
 }
 
-pub(crate) async fn load_filtered_policy<'a>(
+/// Hands the rules `filter` selects to `on_rule` as they arrive.
+pub(crate) async fn load_policy_filter(
     conn: &ConnectionPool,
-    filter: &Filter<'_>,
-) -> Result<Vec<CasbinRule>> {
+    table: &Table,
+    filter: &PolicyFilter,
+    filter_match: FilterMatch,
+    on_rule: &mut (dyn FnMut(CasbinRule) + Send),
+) -> Result<()> {
+    let mut params = Params::default();
+    let predicate = filter_where(table, filter, filter_match, &mut params)?;
     let client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    // Filters take too many shapes to go through the statement cache.
+    let stmt = client
+        .prepare_typed(&rules_query(table, &predicate), &params.types)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    query_rules(&client, &stmt, &params, on_rule).await
+}
 
-    let (g_filter, p_filter) = filtered_where_values(filter);
+/// `SELECT` of the rules satisfying `predicate`.
+fn rules_query(table: &Table, predicate: &str) -> String {
+    format!(
+        "SELECT {} FROM {} WHERE {}",
+        table.select_columns(),
+        table,
+        predicate
+    )
+}
 
-    let stmt = client
-        .prepare_typed("SELECT * from casbin_rule WHERE (
-            ptype LIKE 'g%' AND v0 LIKE $1 AND v1 LIKE $2 AND v2 LIKE $3 AND v3 LIKE $4 AND v4 LIKE $5 AND v5 LIKE $6 )
-        OR (
-            ptype LIKE 'p%' AND v0 LIKE $7 AND v1 LIKE $8 AND v2 LIKE $9 AND v3 LIKE $10 AND v4 LIKE $11 AND v5 LIKE $12 )", &[
-            Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT,
-            Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT,
-            ])
-        .await
-        .map_err(|err| {
-            CasbinError::from(AdapterError(Box::new(Error::PostgresError(
-                err,
-            ))))
-        })?;
-    let casbin_rule: Vec<CasbinRule> = client
-        .query(
-            &stmt,
-            &[
-                &g_filter[0],
-                &g_filter[1],
-                &g_filter[2],
-                &g_filter[3],
-                &g_filter[4],
-                &g_filter[5],
-                &p_filter[0],
-                &p_filter[1],
-                &p_filter[2],
-                &p_filter[3],
-                &p_filter[4],
-                &p_filter[5],
-            ],
-        )
+/// Streams the rules `stmt` selects to `on_rule`, without buffering the
+/// result.
+async fn query_rules(
+    client: &Client,
+    stmt: &Statement,
+    params: &Params<'_>,
+    on_rule: &mut (dyn FnMut(CasbinRule) + Send),
+) -> Result<()> {
+    let rows = client
+        .query_raw(stmt, params.values.iter().copied())
         .await
-        // .map(|s| s.into())
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?
-        .into_iter()
-        .map(CasbinRule::from)
-        .collect();
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    pin_mut!(rows);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?
+    {
+        on_rule(CasbinRule::from(row));
+    }
+    Ok(())
+}
+
+/// Bound parameters of a statement under construction, with their types.
+#[derive(Default)]
+struct Params<'a> {
+    values: Vec<&'a (dyn ToSql + Sync)>,
+    types: Vec<Type>,
+}
 
-    Ok(casbin_rule)
+impl<'a> Params<'a> {
+    /// Binds `value` and returns its placeholder.
+    fn bind(&mut self, value: &'a (dyn ToSql + Sync), ty: Type) -> String {
+        self.values.push(value);
+        self.types.push(ty);
+        format!("${}", self.values.len())
+    }
 }
 
-fn filtered_where_values<'a>(filter: &Filter<'a>) -> ([&'a str; 6], [&'a str; 6]) {
-    let mut g_filter: [&'a str; 6] = ["%", "%", "%", "%", "%", "%"];
-    let mut p_filter: [&'a str; 6] = ["%", "%", "%", "%", "%", "%"];
-    for (idx, val) in filter.g.iter().enumerate() {
-        if val != &"" {
-            g_filter[idx] = val;
+/// `WHERE` clause keeping the rules that satisfy any alternative of
+/// `filter`. No alternatives match nothing.
+fn filter_where<'a>(
+    table: &Table,
+    filter: &'a PolicyFilter,
+    filter_match: FilterMatch,
+    params: &mut Params<'a>,
+) -> Result<String> {
+    let mut clauses = vec![];
+    for (ptype, rule) in filter.alternatives() {
+        let (name, ptype) = match ptype {
+            PtypeMatch::Exact(name) => (name, format!("ptype = {}", params.bind(name, Type::TEXT))),
+            PtypeMatch::Prefix(prefix) => (
+                prefix,
+                prefix_predicate("ptype", &params.bind(prefix, Type::TEXT)),
+            ),
+        };
+        if let Some(max) = table.max_fields() {
+            if rule.width() > max {
+                return Err(rule_too_long(table, name, rule.width()));
+            }
+        }
+        let mut predicates = vec![ptype];
+        for (idx, condition) in rule.fields() {
+            // Values past the end of an array compare as empty strings, like
+            // the unused columns of the `Columns` layout.
+            let field = match table.layout {
+                StorageLayout::Columns => table.field(*idx),
+                _ => format!("COALESCE({}, '')", table.field(*idx)),
+            };
+            predicates.push(field_predicate(&field, condition, filter_match, params));
         }
+        clauses.push(format!("( {} )", predicates.join(" AND ")));
     }
-    for (idx, val) in filter.p.iter().enumerate() {
-        if val != &"" {
-            p_filter[idx] = val;
+    if clauses.is_empty() {
+        Ok("FALSE".to_owned())
+    } else {
+        Ok(clauses.join("\n            OR "))
+    }
+}
+
+fn field_predicate<'a>(
+    field: &str,
+    condition: &'a FieldMatch,
+    filter_match: FilterMatch,
+    params: &mut Params<'a>,
+) -> String {
+    match condition {
+        FieldMatch::Eq(value) => {
+            let operator = match filter_match {
+                FilterMatch::Exact => "=",
+                FilterMatch::Pattern => "LIKE",
+            };
+            format!("{} {} {}", field, operator, params.bind(value, Type::TEXT))
+        }
+        FieldMatch::In(values) => {
+            format!("{} = ANY({})", field, params.bind(values, Type::TEXT_ARRAY))
         }
+        FieldMatch::Prefix(prefix) => prefix_predicate(field, &params.bind(prefix, Type::TEXT)),
+        FieldMatch::Not(condition) => format!(
+            "NOT ({})",
+            field_predicate(field, condition, filter_match, params)
+        ),
+        FieldMatch::Any(conditions) if conditions.is_empty() => "FALSE".to_owned(),
+        FieldMatch::Any(conditions) => format!(
+            "({})",
+            conditions
+                .iter()
+                .map(|condition| field_predicate(field, condition, filter_match, params))
+                .collect::<Vec<_>>()
+                .join(" OR ")
+        ),
     }
-    (g_filter, p_filter)
+}
+
+/// Whether `field` starts with the text bound to `placeholder`. `LIKE` with
+/// the prefix's wildcards escaped, as `starts_with` needs PostgreSQL 11.
+fn prefix_predicate(field: &str, placeholder: &str) -> String {
+    format!(
+        "{} LIKE replace(replace(replace({}, '\\', '\\\\'), '%', '\\%'), '_', '\\_') || '%'",
+        field, placeholder
+    )
 }
 
 pub(crate) async fn save_policy(
     conn: &ConnectionPool,
+    table: &Table,
     rules: Vec<NewCasbinRule<'_>>,
+    copy_threshold: usize,
 ) -> Result<()> {
     let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
-    let (stmt_delete, stmt_insert) = try_join!(
-        client.prepare_typed("DELETE FROM casbin_rule", &[]),
-        client.prepare_typed(
-            "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
-                 VALUES ( $1, $2, $3, $4, $5, $6, $7 ) RETURNING id",
-            &[
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-            ],
-        ),
-    )
-    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let arrays = RuleArrays::new(table, &rules);
+    let sql_delete = format!("DELETE FROM {}", table);
+    let sql_insert = format!(
+        "INSERT INTO {} ( ptype, {} )
+                 {}",
+        table,
+        table.rule_columns(),
+        arrays.select(table)
+    );
+    let stmt_delete = prepare_cached(&client, table, &sql_delete, &[]).await?;
+    let stmt_insert = prepare_cached(&client, table, &sql_insert, &arrays.types()).await?;
 
     let transaction = client
         .transaction()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
     transaction
         .query(&stmt_delete, &[])
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    for rule in rules {
-        transaction
-            .query_one(
-                &stmt_insert,
-                &[
-                    &rule.ptype,
-                    &rule.v0,
-                    &rule.v1,
-                    &rule.v2,
-                    &rule.v3,
-                    &rule.v4,
-                    &rule.v5,
-                ],
-            )
-            .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+    if rules.len() >= copy_threshold {
+        copy_rules(&transaction, &table.to_string(), table, &rules).await?;
+    } else {
+        for chunk in rules.chunks(CHUNK_SIZE) {
+            let values = arrays.bind(chunk);
+            let params = values
+                .iter()
+                .map(|array| array as &(dyn ToSql + Sync))
+                .collect::<Vec<_>>();
+            transaction
+                .execute(&stmt_insert, &params)
+                .await
+                .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+        }
     }
+    notify(
+        &transaction,
+        table,
+        "save_policy",
+        Some(PolicyOperation::Replace),
+    )
+    .await?;
     transaction
         .commit()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
     Ok(())
 }
 
-pub(crate) async fn add_policy(conn: &ConnectionPool, rule: NewCasbinRule<'_>) -> Result<bool> {
-    let client = conn
+/// Makes the stored rules equal to `rules` in one transaction, inserting the
+/// missing rules and deleting the stale ones. Returns how many rules were
+/// inserted and deleted.
+pub(crate) async fn save_policy_diff(
+    conn: &ConnectionPool,
+    table: &Table,
+    rules: Vec<NewCasbinRule<'_>>,
+    copy_threshold: usize,
+) -> Result<(u64, u64)> {
+    const STAGED: &str = "casbin_save_policy";
+    let staged = format!("pg_temp.{}", STAGED);
+    let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
-    let stmt = client
-        .prepare_typed(
-            "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
-                 VALUES ( $1, $2, $3, $4, $5, $6, $7 )
-              RETURNING id",
-            &[
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-            ],
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    let same_rule = format!(
+        "staged.ptype = stored.ptype AND
+                        ROW( {} ) = ROW( {} )",
+        table.rule_columns_of("staged"),
+        table.rule_columns_of("stored")
+    );
+    let mut delete_types = vec![];
+    let (sql_delete, delete_channel) = returning(
+        table,
+        format!(
+            "DELETE FROM {} AS stored WHERE NOT EXISTS (
+                    SELECT 1 FROM {} AS staged WHERE {}
+                 )",
+            table, staged, same_rule
+        ),
+        Logged::Row(PolicyOperation::Remove),
+        None,
+        &mut delete_types,
+    );
+    let mut insert_types = vec![];
+    let (sql_insert, insert_channel) = returning(
+        table,
+        format!(
+            "INSERT INTO {} ( ptype, {} )
+                 SELECT DISTINCT staged.ptype, {} FROM {} AS staged
+                 WHERE NOT EXISTS (
+                    SELECT 1 FROM {} AS stored WHERE {}
+                 )",
+            table,
+            table.rule_columns(),
+            table.rule_columns_of("staged"),
+            staged,
+            table,
+            same_rule
+        ),
+        Logged::Row(PolicyOperation::Add),
+        None,
+        &mut insert_types,
+    );
+
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    stage_rules(&transaction, table, STAGED, &rules, copy_threshold).await?;
+    let (stmt_delete, stmt_insert) = try_join!(
+        transaction.prepare_typed(&sql_delete, &delete_types),
+        transaction.prepare_typed(&sql_insert, &insert_types),
+    )
+    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    let deleted = transaction
+        .query(
+            &stmt_delete,
+            &delete_channel.into_iter().collect::<Vec<_>>(),
         )
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    client
-        .query_one(
-            &stmt,
-            &[
-                &rule.ptype,
-                &rule.v0,
-                &rule.v1,
-                &rule.v2,
-                &rule.v3,
-                &rule.v4,
-                &rule.v5,
-            ],
+        .map(|rows| rows.len() as u64)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let inserted = transaction
+        .query(
+            &stmt_insert,
+            &insert_channel.into_iter().collect::<Vec<_>>(),
         )
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map(|rows| rows.len() as u64)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    if inserted + deleted > 0 {
+        notify(&transaction, table, "save_policy", None).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok((inserted, deleted))
+}
+
+pub(crate) async fn add_policy(
+    conn: &ConnectionPool,
+    table: &Table,
+    rule: NewCasbinRule<'_>,
+) -> Result<bool> {
+    let client = conn
+        .get()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let mut types = table.rule_types();
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "INSERT INTO {} ( ptype, {} )
+                 VALUES ( $1, {} )",
+            table,
+            table.rule_columns(),
+            table.rule_values(2)
+        ),
+        Logged::Row(PolicyOperation::Add),
+        Some("add_policy"),
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
+    let mut params = rule_params(table, &rule.ptype, &rule.values);
+    params.extend(channel);
+    client
+        .query_one(&stmt, &params)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
     Ok(true)
 }
 
+/// Inserts `rules` in one transaction and returns, for each of them, whether
+/// it was newly inserted. Rules already stored fail the whole batch or are
+/// skipped, as `on_conflict` says.
 pub(crate) async fn add_policies(
     conn: &ConnectionPool,
+    table: &Table,
     rules: Vec<NewCasbinRule<'_>>,
-) -> Result<bool> {
+    on_conflict: OnConflict,
+) -> Result<Vec<bool>> {
     let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    let stmt = client
-        .prepare_typed(
-            "INSERT INTO casbin_rule ( ptype, v0, v1, v2, v3, v4, v5 )
-                 VALUES ( $1, $2, $3, $4, $5, $6, $7 )
-              RETURNING id",
-            &[
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-                Type::TEXT,
-            ],
-        )
+    let arrays = RuleArrays::new(table, &rules);
+    let mut types = arrays.types();
+    let (sql, channel) = returning(
+        table,
+        format!(
+            "INSERT INTO {} ( ptype, {} )
+                 {}{}",
+            table,
+            table.rule_columns(),
+            arrays.select(table),
+            conflict_clause(table, on_conflict)
+        ),
+        Logged::Row(PolicyOperation::Add),
+        None,
+        &mut types,
+    );
+    let stmt = prepare_cached(&client, table, &sql, &types).await?;
+
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let mut rows = vec![];
+    for chunk in rules.chunks(CHUNK_SIZE) {
+        let values = arrays.bind(chunk);
+        let mut params = values
+            .iter()
+            .map(|array| array as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        params.extend(channel);
+        rows.extend(
+            transaction
+                .query(&stmt, &params)
+                .await
+                .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?,
+        );
+    }
+    let inserted = changed_rules(&rules, &rows);
+    if inserted.contains(&true) {
+        notify(&transaction, table, "add_policies", None).await?;
+    }
+    transaction
+        .commit()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok(inserted)
+}
 
+/// Adds `rules` in one transaction through a binary `COPY` and returns how
+/// many were inserted. Unlike [`add_policies`], it does not report which.
+pub(crate) async fn add_policies_copy(
+    conn: &ConnectionPool,
+    table: &Table,
+    rules: Vec<NewCasbinRule<'_>>,
+    on_conflict: OnConflict,
+) -> Result<u64> {
+    const STAGED: &str = "casbin_add_policies";
+    let mut client = conn
+        .get()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
     let transaction = client
         .transaction()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    for rule in rules {
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    let inserted = if on_conflict == OnConflict::Fail && table.changelog.is_none() {
+        copy_rules(&transaction, &table.to_string(), table, &rules).await?
+    } else {
+        // Skipping conflicts and logging changes need an INSERT, fed from a
+        // staging table.
+        stage_rules(&transaction, table, STAGED, &rules, 0).await?;
+        let mut types = vec![];
+        let (sql, channel) = returning(
+            table,
+            format!(
+                "INSERT INTO {} ( ptype, {} )
+                 SELECT ptype, {} FROM pg_temp.{}{}",
+                table,
+                table.rule_columns(),
+                table.rule_columns(),
+                STAGED,
+                conflict_clause(table, on_conflict)
+            ),
+            Logged::Row(PolicyOperation::Add),
+            None,
+            &mut types,
+        );
+        let stmt = transaction
+            .prepare_typed(&sql, &types)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
         transaction
-            .query_one(
-                &stmt,
-                &[
-                    &rule.ptype,
-                    &rule.v0,
-                    &rule.v1,
-                    &rule.v2,
-                    &rule.v3,
-                    &rule.v4,
-                    &rule.v5,
-                ],
-            )
+            .query(&stmt, &channel.into_iter().collect::<Vec<_>>())
             .await
-            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+            .map(|rows| rows.len() as u64)
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?
+    };
+    if inserted > 0 {
+        notify(&transaction, table, "add_policies", None).await?;
     }
     transaction
         .commit()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
-    Ok(true)
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok(inserted)
 }
 
-pub(crate) async fn clear_policy(conn: &ConnectionPool) -> Result<()> {
+/// `ON CONFLICT` clause ending an insert into the rule table. Skipping only
+/// applies to the unique key over the rule, so that other constraints still
+/// fail the insert.
+fn conflict_clause(table: &Table, on_conflict: OnConflict) -> String {
+    match on_conflict {
+        OnConflict::Fail => String::new(),
+        OnConflict::Skip => format!(
+            "\n                 ON CONFLICT ( ptype, {} ) DO NOTHING",
+            table.rule_columns()
+        ),
+    }
+}
+
+pub(crate) async fn clear_policy(conn: &ConnectionPool, table: &Table) -> Result<()> {
     let mut client = conn
         .get()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PoolError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
 
-    let stmt = client
-        .prepare_typed("DELETE FROM casbin_rule", &[])
-        .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+    let stmt = prepare_cached(&client, table, &format!("DELETE FROM {}", table), &[]).await?;
 
     let transaction = client
         .transaction()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
     transaction
         .query(&stmt, &[])
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+
+    notify(
+        &transaction,
+        table,
+        "clear_policy",
+        Some(PolicyOperation::Clear),
+    )
+    .await?;
 
     transaction
         .commit()
         .await
-        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::PostgresError(err)))))?;
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    Ok(())
+}
+
+/// Creates `pg_temp.{name}`, a temporary table with the columns of the rule
+/// table that is dropped on commit, and fills it with `rules`. Batches of
+/// `copy_threshold` rules or more are written with a binary `COPY`, smaller
+/// ones sent as one array per value.
+async fn stage_rules(
+    transaction: &Transaction<'_>,
+    table: &Table,
+    name: &str,
+    rules: &[NewCasbinRule<'_>],
+    copy_threshold: usize,
+) -> Result<()> {
+    transaction
+        .batch_execute(&format!(
+            "CREATE TEMPORARY TABLE {} (
+                    ptype VARCHAR NOT NULL,
+                    {}
+                 ) ON COMMIT DROP",
+            name,
+            table.column_definitions()
+        ))
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let staged = format!("pg_temp.{}", name);
+    if rules.len() >= copy_threshold {
+        copy_rules(transaction, &staged, table, rules).await?;
+        return Ok(());
+    }
+
+    let arrays = RuleArrays::new(table, rules);
+    let stmt = transaction
+        .prepare_typed(
+            &format!(
+                "INSERT INTO {} ( ptype, {} )
+                 {}",
+                staged,
+                table.rule_columns(),
+                arrays.select(table)
+            ),
+            &arrays.types(),
+        )
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    for chunk in rules.chunks(CHUNK_SIZE) {
+        let values = arrays.bind(chunk);
+        let params = values
+            .iter()
+            .map(|array| array as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        transaction
+            .execute(&stmt, &params)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    }
+    Ok(())
+}
+
+/// Rules per statement of a batch. Each statement binds every value of its
+/// rules, so this bounds the size of its parameters.
+const CHUNK_SIZE: usize = 5000;
+
+/// Rules bound as parallel `TEXT[]` parameters, one array for the ptypes and
+/// one per value, and read back as rows by [`RuleArrays::select`].
+struct RuleArrays {
+    width: usize,
+}
+
+impl RuleArrays {
+    /// Arrays for as many values as the longest of `rules` has, rounded up
+    /// to a power of two on array layouts so that their statements take
+    /// few shapes. Array layouts pad shorter rules with `NULL`s.
+    fn new(table: &Table, rules: &[NewCasbinRule<'_>]) -> Self {
+        let width = match table.max_fields() {
+            Some(max) => max,
+            None => rules
+                .iter()
+                .map(|rule| rule.values.len())
+                .max()
+                .unwrap_or(0)
+                .max(1)
+                .next_power_of_two(),
+        };
+        Self { width }
+    }
+
+    /// `SELECT` of the rules bound from `$1`, as rows of `ptype` and the
+    /// rule columns in their stored form.
+    fn select(&self, table: &Table) -> String {
+        let values = (0..self.width)
+            .map(|i| format!("v{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let columns = match table.layout {
+            StorageLayout::Columns => values.clone(),
+            StorageLayout::TextArray => format!("array_remove(ARRAY[{}], NULL) AS rule", values),
+            StorageLayout::Jsonb => {
+                format!("to_jsonb(array_remove(ARRAY[{}], NULL)) AS rule", values)
+            }
+        };
+        format!(
+            "SELECT ptype, {} FROM UNNEST( {} ) AS input ( ptype, {} )",
+            columns,
+            (1..=self.width + 1)
+                .map(|i| format!("${}::text[]", i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            values
+        )
+    }
+
+    fn types(&self) -> Vec<Type> {
+        vec![Type::TEXT_ARRAY; self.width + 1]
+    }
+
+    /// The arrays holding `rules`, in parameter order.
+    fn bind<'a>(&self, rules: &[NewCasbinRule<'a>]) -> Vec<Vec<Option<&'a str>>> {
+        let mut arrays = vec![rules.iter().map(|rule| Some(rule.ptype)).collect()];
+        arrays.extend((0..self.width).map(|idx| {
+            rules
+                .iter()
+                .map(|rule| rule.values.get(idx).copied())
+                .collect()
+        }));
+        arrays
+    }
+}
+
+/// For each of `rules`, whether it is among `rows` as returned by
+/// [`returning`]. A row accounts for one rule only, so of equal rules only
+/// the first counts as changed.
+fn changed_rules(rules: &[NewCasbinRule<'_>], rows: &[Row]) -> Vec<bool> {
+    let mut changed: HashMap<(String, Vec<String>), usize> = HashMap::new();
+    for row in rows {
+        *changed
+            .entry((row.get("ptype"), row.get("rule")))
+            .or_default() += 1;
+    }
+    rules
+        .iter()
+        .map(|rule| {
+            let key = (
+                rule.ptype.to_owned(),
+                rule.values.iter().map(|value| value.to_string()).collect(),
+            );
+            match changed.get_mut(&key) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    true
+                }
+                _ => false,
+            }
+        })
+        .collect()
+}
+
+/// Writes `rules` into `target`, which has the columns of the rule table,
+/// with a binary `COPY`. Returns the number of rules written.
+async fn copy_rules(
+    transaction: &Transaction<'_>,
+    target: &str,
+    table: &Table,
+    rules: &[NewCasbinRule<'_>],
+) -> Result<u64> {
+    let sink = transaction
+        .copy_in(&format!(
+            "COPY {} ( ptype, {} ) FROM STDIN BINARY",
+            target,
+            table.rule_columns()
+        ))
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let writer = BinaryCopyInWriter::new(sink, &table.column_types());
+    pin_mut!(writer);
+    for rule in rules {
+        let jsonb;
+        let mut row: Vec<&(dyn ToSql + Sync)> = vec![&rule.ptype];
+        match table.layout {
+            StorageLayout::Columns => {
+                row.extend(rule.values.iter().map(|v| v as &(dyn ToSql + Sync)))
+            }
+            StorageLayout::TextArray => row.push(&rule.values),
+            StorageLayout::Jsonb => {
+                jsonb = JsonbRule(&rule.values);
+                row.push(&jsonb);
+            }
+        }
+        writer
+            .as_mut()
+            .write(&row)
+            .await
+            .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    }
+    writer
+        .finish()
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))
+}
+
+/// Hit and miss counts of the statement caches for the statements of one
+/// adapter and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct StatementCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+}
+
+/// Prepares `sql` through the statement cache of `client`'s connection,
+/// counting whether it was already cached. Only statements whose text takes
+/// few shapes go through here, so that the caches stay small: those over
+/// [`RuleArrays`] take one per power of two up to the longest rule.
+async fn prepare_cached(
+    client: &Client,
+    table: &Table,
+    sql: &str,
+    types: &[Type],
+) -> Result<Statement> {
+    let cached = client.statement_cache.size();
+    let stmt = client
+        .prepare_typed_cached(sql, types)
+        .await
+        .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+    let counter = if client.statement_cache.size() > cached {
+        &table.statements.misses
+    } else {
+        &table.statements.hits
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    Ok(stmt)
+}
+
+/// What the changelog records for each row changed by a statement.
+#[derive(Clone, Copy)]
+enum Logged {
+    /// The row as it is returned, as `operation`.
+    Row(PolicyOperation),
+    /// The removal of the row's `prev_rule` and the addition of the row as
+    /// it is returned.
+    Update,
+}
+
+/// Completes a data-modifying statement on the rule table so that it returns
+/// one row per changed rule, with its `ptype` and its values as a `TEXT[]`
+/// `rule`.
+///
+/// With a changelog, every changed rule is recorded there as `logged` says
+/// and the new entry's id is published on the notification channel, if any.
+/// Without one, `op` is published instead when both it and a channel are
+/// given; statements run once per rule inside a transaction pass `None` and
+/// leave that to [`notify`]. Notifications are only delivered on commit.
+///
+/// The channel, when used, is one more `TEXT` parameter after `types`; it is
+/// returned ready to be bound last.
+fn returning<'a>(
+    table: &'a Table,
+    dml: String,
+    logged: Logged,
+    op: Option<&'static str>,
+    types: &mut Vec<Type>,
+) -> (String, Option<&'a (dyn ToSql + Sync)>) {
+    let channel = table
+        .notify_channel
+        .as_ref()
+        .map(|channel| channel as &(dyn ToSql + Sync));
+    match (&table.changelog, channel, op) {
+        (Some(changelog), channel, _) => {
+            let notify = match channel {
+                Some(_) => {
+                    types.push(Type::TEXT);
+                    format!(", pg_notify(${}, id::text)", types.len())
+                }
+                None => String::new(),
+            };
+            let (returned, entries) = match logged {
+                Logged::Row(operation) => (
+                    format!("ptype, {}", table.rule_columns()),
+                    format!(
+                        "SELECT '{}', ptype, {} FROM changed",
+                        operation,
+                        table.rule_array()
+                    ),
+                ),
+                Logged::Update => (
+                    format!("ptype, prev_rule, {}", table.rule_columns()),
+                    format!(
+                        "SELECT change.operation, ptype, change.rule FROM changed
+                         CROSS JOIN LATERAL (
+                            VALUES ( '{}', prev_rule ), ( '{}', {} )
+                         ) AS change ( operation, rule )",
+                        PolicyOperation::Remove,
+                        PolicyOperation::Add,
+                        table.rule_array()
+                    ),
+                ),
+            };
+            let sql = format!(
+                "WITH changed AS ( {} RETURNING {} ),
+                  logged AS (
+                    INSERT INTO {} ( operation, ptype, rule )
+                    {}
+                    RETURNING id, ptype, rule
+                  )
+                SELECT id, ptype, rule{} FROM logged",
+                dml, returned, changelog, entries, notify
+            );
+            (sql, channel)
+        }
+        (None, Some(channel), Some(op)) => {
+            types.push(Type::TEXT);
+            let sql = format!(
+                "WITH changed AS ( {} RETURNING id, ptype, {} AS rule )
+                SELECT id, ptype, rule, pg_notify(${}, '{}') FROM changed",
+                dml,
+                table.rule_array(),
+                types.len(),
+                op
+            );
+            (sql, Some(channel))
+        }
+        _ => (
+            format!(
+                "{} RETURNING id, ptype, {} AS rule",
+                dml,
+                table.rule_array()
+            ),
+            None,
+        ),
+    }
+}
+
+/// Publishes `op` on the notification channel, if any, once `transaction`
+/// commits. With a changelog the statements have already recorded and
+/// published each changed rule, so only operations replacing the whole
+/// policy, given as `reset`, are recorded here as a single entry.
+async fn notify(
+    transaction: &Transaction<'_>,
+    table: &Table,
+    op: &str,
+    reset: Option<PolicyOperation>,
+) -> Result<()> {
+    match (&table.changelog, &table.notify_channel) {
+        (Some(changelog), channel) => {
+            if let Some(operation) = reset {
+                let id: i64 = transaction
+                    .query_one(
+                        &format!(
+                            "INSERT INTO {} ( operation ) VALUES ( $1 ) RETURNING id",
+                            changelog
+                        ),
+                        &[&operation.to_string()],
+                    )
+                    .await
+                    .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?
+                    .get(0);
+                if let Some(channel) = channel {
+                    transaction
+                        .execute("SELECT pg_notify($1, $2)", &[channel, &id.to_string()])
+                        .await
+                        .map_err(|err| {
+                            CasbinError::from(AdapterError(Box::new(Error::from(err))))
+                        })?;
+                }
+            }
+        }
+        (None, Some(channel)) => {
+            transaction
+                .execute("SELECT pg_notify($1, $2)", &[channel, &op])
+                .await
+                .map_err(|err| CasbinError::from(AdapterError(Box::new(Error::from(err)))))?;
+        }
+        (None, None) => {}
+    }
     Ok(())
 }
 
-fn normalize_casbin_rule(mut rule: Vec<String>) -> Vec<String> {
-    rule.resize(6, String::new());
-    rule
+/// Predicate matching the values from `field_index` on against
+/// `field_values`, parameters from `$start`. Empty strings match anything
+/// and are not bound.
+fn filter_predicate<'a>(
+    table: &Table,
+    field_index: usize,
+    field_values: &'a [String],
+    start: usize,
+) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
+    let (predicates, params): (Vec<_>, Vec<_>) = field_values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| !value.is_empty())
+        .enumerate()
+        .map(|(offset, (idx, value))| {
+            (
+                format!("{} = ${}", table.field(field_index + idx), start + offset),
+                value as &(dyn ToSql + Sync),
+            )
+        })
+        .unzip();
+    if predicates.is_empty() {
+        ("TRUE".to_owned(), params)
+    } else {
+        (predicates.join(" AND "), params)
+    }
+}
+
+/// Binds `ptype` and the rule values. Column layouts take one parameter per
+/// value, array layouts bind the whole `Vec` as a single `TEXT[]`.
+#[allow(clippy::ptr_arg)]
+fn rule_params<'a, T: ToSql + Sync>(
+    table: &Table,
+    ptype: &'a (dyn ToSql + Sync),
+    values: &'a Vec<T>,
+) -> Vec<&'a (dyn ToSql + Sync)> {
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(values.len() + 1);
+    params.push(ptype);
+    match table.layout {
+        StorageLayout::Columns => params.extend(values.iter().map(|v| v as &(dyn ToSql + Sync))),
+        StorageLayout::TextArray | StorageLayout::Jsonb => params.push(values),
+    }
+    params
+}
+
+pub(crate) fn rule_too_long(table: &Table, ptype: &str, len: usize) -> CasbinError {
+    CasbinError::from(AdapterError(Box::new(Error::RuleTooLong {
+        ptype: ptype.to_owned(),
+        len,
+        max: table.fields,
+    })))
+}
+
+/// Brings rule values into the shape stored by the table: padded with empty
+/// strings for column layouts, trailing empty strings dropped for arrays.
+pub(crate) fn normalize_casbin_rule<T>(
+    table: &Table,
+    pt: &str,
+    mut values: Vec<T>,
+) -> Result<Vec<T>>
+where
+    T: AsRef<str> + Clone + Default,
+{
+    match table.max_fields() {
+        Some(max) => {
+            if values.len() > max {
+                return Err(rule_too_long(table, pt, values.len()));
+            }
+            values.resize(max, T::default());
+        }
+        None => {
+            while values.last().is_some_and(|v| v.as_ref().is_empty()) {
+                values.pop();
+            }
+        }
+    }
+    Ok(values)
 }
@@ -1,12 +1,101 @@
+use casbin::{error::AdapterError, Error as CasbinError};
 use deadpool_postgres::{BuildError, PoolError};
-use std::{error::Error as StdError, fmt};
-use tokio_postgres::Error as PostgresError;
+use std::{error::Error as StdError, fmt, io};
+use tokio_postgres::{error::SqlState, Error as PostgresError};
+
+use crate::retry::RetryClass;
 
 #[derive(Debug)]
 pub enum Error {
     PostgresError(PostgresError),
     PoolError(PoolError),
     BuildError(BuildError),
+    InvalidConfig(String),
+    MissingEnvVar(&'static str),
+    InvalidEnvVar(&'static str, String),
+    RuleTooLong {
+        ptype: String,
+        len: usize,
+        max: usize,
+    },
+    SchemaMismatch {
+        table: String,
+        problems: Vec<String>,
+    },
+    /// A rule `remove_policies` was asked to remove is not stored, with
+    /// [`OnMissing::Fail`](crate::OnMissing::Fail).
+    RuleMissing {
+        ptype: String,
+        rule: Vec<String>,
+    },
+    /// A unique or exclusion constraint rejected the change.
+    Conflict(PostgresError),
+    /// A table, column or schema the statement refers to does not exist.
+    NotFound(PostgresError),
+    /// No pooled connection became free in time, or the server cancelled
+    /// the statement or gave up waiting for a lock.
+    Timeout(Box<dyn StdError + Send + Sync>),
+    /// The connection to the server failed, was closed or is shutting down.
+    ConnectionLost(Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    /// Whether running the operation again may succeed: timeouts, lost
+    /// connections, serialization failures and deadlocks. See
+    /// [`RetryClass`].
+    pub fn is_retryable(&self) -> bool {
+        RetryClass::of(self).is_some()
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Error::Conflict(_))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound(_))
+    }
+
+    /// The SQLSTATE code the server reported, if any.
+    pub fn code(&self) -> Option<&SqlState> {
+        match self {
+            Error::PostgresError(pg_err) | Error::Conflict(pg_err) | Error::NotFound(pg_err) => {
+                pg_err.code()
+            }
+            Error::Timeout(err) | Error::ConnectionLost(err) => err
+                .downcast_ref::<PostgresError>()
+                .and_then(PostgresError::code),
+            _ => None,
+        }
+    }
+}
+
+/// Access to the adapter's [`Error`] inside the `casbin::Error` its methods
+/// return.
+pub trait AdapterErrorExt {
+    /// The adapter error this is, if any.
+    fn adapter_error(&self) -> Option<&Error>;
+
+    /// See [`Error::is_retryable`].
+    fn is_retryable(&self) -> bool {
+        self.adapter_error().is_some_and(Error::is_retryable)
+    }
+
+    fn is_conflict(&self) -> bool {
+        self.adapter_error().is_some_and(Error::is_conflict)
+    }
+
+    fn is_not_found(&self) -> bool {
+        self.adapter_error().is_some_and(Error::is_not_found)
+    }
+}
+
+impl AdapterErrorExt for CasbinError {
+    fn adapter_error(&self) -> Option<&Error> {
+        match self {
+            CasbinError::AdapterError(AdapterError(err)) => err.downcast_ref::<Error>(),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -17,6 +106,29 @@ impl fmt::Display for Error {
             PostgresError(pg_err) => pg_err.fmt(f),
             PoolError(pg_err) => pg_err.fmt(f),
             BuildError(pg_err) => pg_err.fmt(f),
+            InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            MissingEnvVar(name) => write!(f, "environment variable `{}` is not set", name),
+            InvalidEnvVar(name, msg) => {
+                write!(f, "environment variable `{}` is invalid: {}", name, msg)
+            }
+            RuleTooLong { ptype, len, max } => write!(
+                f,
+                "`{}` rule has {} fields but the table only stores {}",
+                ptype, len, max
+            ),
+            SchemaMismatch { table, problems } => write!(
+                f,
+                "table {} does not match the expected schema: {}",
+                table,
+                problems.join("; ")
+            ),
+            RuleMissing { ptype, rule } => {
+                write!(f, "`{}` rule {:?} is not stored", ptype, rule)
+            }
+            Conflict(pg_err) => write!(f, "conflicting rule: {}", pg_err),
+            NotFound(pg_err) => pg_err.fmt(f),
+            Timeout(err) => write!(f, "timed out: {}", err),
+            ConnectionLost(err) => write!(f, "connection lost: {}", err),
         }
     }
 }
@@ -29,19 +141,67 @@ impl StdError for Error {
             PostgresError(pg_err) => Some(pg_err),
             PoolError(pg_err) => Some(pg_err),
             BuildError(pg_err) => Some(pg_err),
+            Conflict(pg_err) | NotFound(pg_err) => Some(pg_err),
+            Timeout(err) | ConnectionLost(err) => Some(err.as_ref()),
+            InvalidConfig(_)
+            | MissingEnvVar(_)
+            | InvalidEnvVar(..)
+            | RuleTooLong { .. }
+            | SchemaMismatch { .. }
+            | RuleMissing { .. } => None,
         }
     }
 }
 
+/// Classifies the error by its SQLSTATE code, or as a lost connection when
+/// it has none and the connection is closed or failed on I/O.
 impl From<PostgresError> for Error {
     fn from(pg_err: PostgresError) -> Self {
-        Error::PostgresError(pg_err)
+        let code = match pg_err.code() {
+            Some(code) => code,
+            None if pg_err.is_closed()
+                || pg_err
+                    .source()
+                    .is_some_and(|source| source.is::<io::Error>()) =>
+            {
+                return Error::ConnectionLost(Box::new(pg_err))
+            }
+            None => return Error::PostgresError(pg_err),
+        };
+        if *code == SqlState::UNIQUE_VIOLATION || *code == SqlState::EXCLUSION_VIOLATION {
+            Error::Conflict(pg_err)
+        } else if *code == SqlState::UNDEFINED_TABLE
+            || *code == SqlState::UNDEFINED_COLUMN
+            || *code == SqlState::INVALID_SCHEMA_NAME
+        {
+            Error::NotFound(pg_err)
+        } else if *code == SqlState::QUERY_CANCELED || *code == SqlState::LOCK_NOT_AVAILABLE {
+            Error::Timeout(Box::new(pg_err))
+        } else if code.code().starts_with("08")
+            || *code == SqlState::ADMIN_SHUTDOWN
+            || *code == SqlState::CRASH_SHUTDOWN
+            || *code == SqlState::CANNOT_CONNECT_NOW
+        {
+            Error::ConnectionLost(Box::new(pg_err))
+        } else {
+            Error::PostgresError(pg_err)
+        }
     }
 }
 
+/// Waiting for a connection past the pool's timeouts is a
+/// [`Error::Timeout`]; an error opening a connection is classified like any
+/// other server error.
 impl From<PoolError> for Error {
     fn from(pg_err: PoolError) -> Self {
-        Error::PoolError(pg_err)
+        match pg_err {
+            PoolError::Timeout(_) => Error::Timeout(Box::new(pg_err)),
+            PoolError::Backend(pg_err) => match Error::from(pg_err) {
+                Error::PostgresError(pg_err) => Error::PoolError(PoolError::Backend(pg_err)),
+                err => err,
+            },
+            pg_err => Error::PoolError(pg_err),
+        }
     }
 }
 impl From<BuildError> for Error {
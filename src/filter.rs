@@ -0,0 +1,168 @@
+use casbin::Filter;
+
+/// A condition on one value of a rule.
+///
+/// Values missing from shorter rules compare as empty strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldMatch {
+    /// Equal to the value. A `LIKE` pattern instead when the adapter was
+    /// built with [`FilterMatch::Pattern`](crate::FilterMatch::Pattern).
+    Eq(String),
+    /// Equal to one of the values.
+    In(Vec<String>),
+    /// Starting with the value, taken literally.
+    Prefix(String),
+    /// Not satisfying the condition.
+    Not(Box<FieldMatch>),
+    /// Satisfying at least one of the conditions.
+    Any(Vec<FieldMatch>),
+}
+
+impl FieldMatch {
+    pub fn eq<S: Into<String>>(value: S) -> Self {
+        Self::Eq(value.into())
+    }
+
+    pub fn one_of<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::In(values.into_iter().map(Into::into).collect())
+    }
+
+    pub fn prefix<S: Into<String>>(prefix: S) -> Self {
+        Self::Prefix(prefix.into())
+    }
+
+    pub fn any<I: IntoIterator<Item = FieldMatch>>(conditions: I) -> Self {
+        Self::Any(conditions.into_iter().collect())
+    }
+
+    /// The negation of this condition.
+    pub fn negate(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+}
+
+/// Conditions on the values of a rule, by index from `v0`, all of which must
+/// hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleMatch {
+    fields: Vec<(usize, FieldMatch)>,
+    /// Number of values the rule is matched against, wildcards included.
+    width: usize,
+}
+
+impl RuleMatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires the value at `idx` to satisfy `condition`, on top of any
+    /// condition given for it before.
+    pub fn field(mut self, idx: usize, condition: FieldMatch) -> Self {
+        self.fields.push((idx, condition));
+        self.width = self.width.max(idx + 1);
+        self
+    }
+
+    /// Equality with each non-empty value, casbin `Filter` style.
+    fn values<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut rule = Self::new();
+        for (idx, value) in values.into_iter().map(Into::into).enumerate() {
+            rule.width = idx + 1;
+            if !value.is_empty() {
+                rule.fields.push((idx, FieldMatch::Eq(value)));
+            }
+        }
+        rule
+    }
+
+    pub(crate) fn fields(&self) -> &[(usize, FieldMatch)] {
+        &self.fields
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+}
+
+/// Which ptypes an alternative of a [`PolicyFilter`] applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PtypeMatch {
+    Exact(String),
+    Prefix(String),
+}
+
+/// Selects the rules `TokioPostgresAdapter::load_policy_filter` loads.
+///
+/// The filter is a list of alternatives, each naming the ptypes it applies
+/// to and the [`RuleMatch`] their rules must satisfy; a rule is loaded when
+/// any alternative selects it, and ptypes no alternative names are not
+/// loaded at all. Unlike a casbin [`Filter`], this tells apart ptypes like
+/// `g` and `g2`. Every value is bound as a query parameter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyFilter {
+    alternatives: Vec<(PtypeMatch, RuleMatch)>,
+}
+
+impl PolicyFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the rules of `ptype` whose values, from `v0` on, equal `values`;
+    /// empty values match anything. Replaces any alternative given for
+    /// `ptype` before.
+    pub fn ptype<P, I, S>(mut self, ptype: P, values: I) -> Self
+    where
+        P: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let ptype = PtypeMatch::Exact(ptype.into());
+        self.alternatives.retain(|(other, _)| *other != ptype);
+        self.alternatives.push((ptype, RuleMatch::values(values)));
+        self
+    }
+
+    /// Also loads the rules of `ptype` satisfying `rule`.
+    pub fn rule<P: Into<String>>(mut self, ptype: P, rule: RuleMatch) -> Self {
+        self.alternatives
+            .push((PtypeMatch::Exact(ptype.into()), rule));
+        self
+    }
+
+    /// Also loads the rules of every ptype starting with `prefix` that
+    /// satisfy `rule`.
+    pub fn ptype_prefix<P: Into<String>>(mut self, prefix: P, rule: RuleMatch) -> Self {
+        self.alternatives
+            .push((PtypeMatch::Prefix(prefix.into()), rule));
+        self
+    }
+
+    pub(crate) fn alternatives(&self) -> &[(PtypeMatch, RuleMatch)] {
+        &self.alternatives
+    }
+}
+
+/// The casbin semantics: `g` values for every ptype starting with `g`, `p`
+/// values for every ptype starting with `p`.
+impl From<&Filter<'_>> for PolicyFilter {
+    fn from(filter: &Filter<'_>) -> Self {
+        Self::new()
+            .ptype_prefix("g", RuleMatch::values(filter.g.iter().copied()))
+            .ptype_prefix("p", RuleMatch::values(filter.p.iter().copied()))
+    }
+}
+
+impl From<Filter<'_>> for PolicyFilter {
+    fn from(filter: Filter<'_>) -> Self {
+        Self::from(&filter)
+    }
+}
@@ -1,7 +1,18 @@
 extern crate tokio_postgres;
 
+#[macro_use]
+mod retry;
+
 mod adapter;
+mod builder;
+mod changelog;
 mod error;
+mod filter;
+mod migrations;
+mod table;
+mod tls;
+#[cfg(feature = "watcher")]
+mod watcher;
 
 #[macro_use]
 mod models;
@@ -9,6 +20,22 @@ mod models;
 mod actions;
 
 pub use casbin;
+#[cfg(feature = "tls-native-tls")]
+pub use postgres_native_tls;
+#[cfg(feature = "tls-rustls")]
+pub use tokio_postgres_rustls;
 
-pub use adapter::TokioPostgresAdapter;
-pub use error::Error;
+pub use adapter::{SaveSummary, StatementCacheStats, TokioPostgresAdapter};
+pub use builder::{
+    FilterMatch, OnConflict, OnMissing, SaveMode, SchemaMode, TokioPostgresAdapterBuilder,
+};
+#[cfg(feature = "watcher")]
+pub use changelog::PolicyChanges;
+pub use changelog::{PolicyChange, PolicyOperation};
+pub use error::{AdapterErrorExt, Error};
+pub use filter::{FieldMatch, PolicyFilter, RuleMatch};
+pub use retry::{RetryClass, RetryPolicy};
+pub use table::{StorageLayout, TableName};
+pub use tls::{SslMode, TlsOptions};
+#[cfg(feature = "watcher")]
+pub use watcher::{PostgresWatcher, RECONNECTED};
@@ -1,40 +1,83 @@
-use tokio_postgres::Row;
+use bytes::{BufMut, BytesMut};
+use std::{error::Error, fmt::Write};
+use tokio_postgres::{
+    types::{to_sql_checked, IsNull, ToSql, Type},
+    Row,
+};
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct CasbinRule {
     pub id: i32,
     pub ptype: String,
-    pub v0: String,
-    pub v1: String,
-    pub v2: String,
-    pub v3: String,
-    pub v4: String,
-    pub v5: String,
+    pub values: Vec<String>,
 }
 
 impl From<Row> for CasbinRule {
     fn from(row: Row) -> Self {
+        let values = if row.columns().iter().any(|column| column.name() == "rule") {
+            row.get("rule")
+        } else {
+            row.columns()
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| is_value_column(column.name()))
+                .map(|(idx, _)| row.get(idx))
+                .collect()
+        };
+
         Self {
             id: row.get("id"),
             ptype: row.get("ptype"),
-            v0: row.get("v0"),
-            v1: row.get("v1"),
-            v2: row.get("v2"),
-            v3: row.get("v3"),
-            v4: row.get("v4"),
-            v5: row.get("v5"),
+            values,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct NewCasbinRule<'a> {
     pub ptype: &'a str,
-    pub v0: &'a str,
-    pub v1: &'a str,
-    pub v2: &'a str,
-    pub v3: &'a str,
-    pub v4: &'a str,
-    pub v5: &'a str,
+    pub values: Vec<&'a str>,
+}
+
+/// Rule values sent as a `JSONB` array of strings, for binary `COPY` into
+/// the `Jsonb` layout.
+#[derive(Debug)]
+pub(crate) struct JsonbRule<'a>(pub &'a [&'a str]);
+
+impl ToSql for JsonbRule<'_> {
+    fn to_sql(&self, _: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        // Version of the binary `JSONB` format, followed by the JSON text.
+        out.put_u8(1);
+        let mut json = String::from("[");
+        for (idx, value) in self.0.iter().enumerate() {
+            if idx > 0 {
+                json.push(',');
+            }
+            json.push('"');
+            for c in value.chars() {
+                match c {
+                    '"' => json.push_str("\\\""),
+                    '\\' => json.push_str("\\\\"),
+                    c if c < ' ' => write!(json, "\\u{:04x}", c as u32)?,
+                    c => json.push(c),
+                }
+            }
+            json.push('"');
+        }
+        json.push(']');
+        out.put_slice(json.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::JSONB
+    }
+
+    to_sql_checked!();
+}
+
+fn is_value_column(name: &str) -> bool {
+    name.strip_prefix('v')
+        .is_some_and(|idx| !idx.is_empty() && idx.bytes().all(|b| b.is_ascii_digit()))
 }
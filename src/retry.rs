@@ -0,0 +1,263 @@
+use casbin::Error as CasbinError;
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
+
+use crate::error::{AdapterErrorExt, Error};
+
+/// A transient failure a [`RetryPolicy`] can retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryClass {
+    /// [`Error::Timeout`]: no pooled connection became free in time, or the
+    /// server cancelled the statement or gave up waiting for a lock.
+    Timeout,
+    /// [`Error::ConnectionLost`]. Only retried for idempotent operations,
+    /// since the transaction may have committed before the connection broke.
+    ConnectionLost,
+    /// SQLSTATE `40001`: the transaction could not be serialized.
+    SerializationFailure,
+    /// SQLSTATE `40P01`: the transaction was chosen to break a deadlock.
+    Deadlock,
+}
+
+impl RetryClass {
+    const ALL: [RetryClass; 4] = [
+        RetryClass::Timeout,
+        RetryClass::ConnectionLost,
+        RetryClass::SerializationFailure,
+        RetryClass::Deadlock,
+    ];
+
+    /// The class of `err`, if it is transient.
+    pub fn of(err: &Error) -> Option<Self> {
+        match err {
+            Error::Timeout(_) => Some(RetryClass::Timeout),
+            Error::ConnectionLost(_) => Some(RetryClass::ConnectionLost),
+            Error::PostgresError(pg_err) => match pg_err.code() {
+                Some(&SqlState::T_R_SERIALIZATION_FAILURE) => {
+                    Some(RetryClass::SerializationFailure)
+                }
+                Some(&SqlState::T_R_DEADLOCK_DETECTED) => Some(RetryClass::Deadlock),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// How the adapter retries operations that failed on a transient error.
+///
+/// Each attempt runs the whole operation again, in a new transaction on a
+/// freshly checked out connection. The wait before a retry doubles with
+/// every attempt, and is drawn at random between half and all of that, so
+/// that adapters failing together do not retry together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    classes: u8,
+}
+
+/// Three attempts, waiting from 50ms up to 2s, on every [`RetryClass`].
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            classes: RetryClass::ALL
+                .iter()
+                .fold(0, |classes, class| classes | class.bit()),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attempts in all, the first one included; `1` never retries.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The wait before the first retry, doubled for each further one up to
+    /// `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Retries only failures of `classes`.
+    pub fn retry_on<I: IntoIterator<Item = RetryClass>>(mut self, classes: I) -> Self {
+        self.classes = classes
+            .into_iter()
+            .fold(0, |classes, class| classes | class.bit());
+        self
+    }
+
+    /// Whether to try again after attempt number `attempt`, counting from 1,
+    /// failed with `err`.
+    pub(crate) fn retries(&self, err: &CasbinError, attempt: u32, idempotent: bool) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match err.adapter_error().and_then(RetryClass::of) {
+            Some(RetryClass::ConnectionLost) if !idempotent => false,
+            Some(class) => self.classes & class.bit() != 0,
+            None => false,
+        }
+    }
+
+    /// The wait after attempt number `attempt` failed.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let nominal = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        nominal / 2 + nominal.mul_f64(rand::random::<f64>() / 2.0)
+    }
+}
+
+/// Awaits `$op`, a future built anew for every attempt, and tries again while
+/// the `Option<RetryPolicy>` retries its error. `$idempotent` tells whether
+/// the operation may run twice when a lost connection leaves it unknown
+/// whether the first attempt committed. Operations that report what they
+/// changed are not: a replay would report the first attempt's changes as
+/// already made.
+macro_rules! retrying {
+    ($policy:expr, $idempotent:expr, $op:expr) => {{
+        let policy: Option<$crate::retry::RetryPolicy> = $policy;
+        let mut attempt = 1;
+        loop {
+            match (policy, $op.await) {
+                (Some(policy), Err(err)) if policy.retries(&err, attempt, $idempotent) => {
+                    ::tokio::time::sleep(policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                (_, result) => break result,
+            }
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use casbin::{error::AdapterError, Result};
+    use std::{
+        collections::VecDeque,
+        io,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Mutex,
+        },
+    };
+
+    /// An operation that fails with each of its faults in turn before it
+    /// succeeds, returning the number of calls.
+    struct FaultyOp {
+        faults: Mutex<VecDeque<Error>>,
+        calls: AtomicU32,
+    }
+
+    impl FaultyOp {
+        fn new(faults: Vec<Error>) -> Self {
+            Self {
+                faults: Mutex::new(faults.into()),
+                calls: AtomicU32::new(0),
+            }
+        }
+
+        async fn run(&self) -> Result<u32> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            match self.faults.lock().unwrap().pop_front() {
+                Some(err) => Err(CasbinError::from(AdapterError(Box::new(err)))),
+                None => Ok(calls),
+            }
+        }
+
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    fn timeout() -> Error {
+        Error::Timeout(Box::new(io::Error::from(io::ErrorKind::TimedOut)))
+    }
+
+    fn connection_lost() -> Error {
+        Error::ConnectionLost(Box::new(io::Error::from(io::ErrorKind::ConnectionReset)))
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test(flavor = "multi_thread"))]
+    async fn test_retry_transient() {
+        let op = FaultyOp::new(vec![timeout(), connection_lost()]);
+        let result = retrying!(Some(policy()), true, op.run());
+        assert_eq!(result.unwrap(), 3);
+
+        // Out of attempts.
+        let op = FaultyOp::new(vec![timeout(), timeout(), timeout()]);
+        let result = retrying!(Some(policy()), true, op.run());
+        assert!(matches!(result, Err(ref err) if err.is_retryable()));
+        assert_eq!(op.calls(), 3);
+
+        let op = FaultyOp::new(vec![]);
+        let result = retrying!(Some(policy().max_attempts(0)), true, op.run());
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test(flavor = "multi_thread"))]
+    async fn test_retry_skipped() {
+        // The first attempt may have committed.
+        let op = FaultyOp::new(vec![connection_lost()]);
+        assert!(retrying!(Some(policy()), false, op.run()).is_err());
+        assert_eq!(op.calls(), 1);
+        let op = FaultyOp::new(vec![timeout()]);
+        assert_eq!(retrying!(Some(policy()), false, op.run()).unwrap(), 2);
+
+        let op = FaultyOp::new(vec![timeout()]);
+        let only_lost = policy().retry_on([RetryClass::ConnectionLost]);
+        assert!(retrying!(Some(only_lost), true, op.run()).is_err());
+        assert_eq!(op.calls(), 1);
+
+        let op = FaultyOp::new(vec![Error::InvalidConfig("broken".to_owned())]);
+        assert!(retrying!(Some(policy()), true, op.run()).is_err());
+        assert_eq!(op.calls(), 1);
+
+        let op = FaultyOp::new(vec![timeout()]);
+        assert!(retrying!(None, true, op.run()).is_err());
+        assert_eq!(op.calls(), 1);
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy =
+            RetryPolicy::new().backoff(Duration::from_millis(100), Duration::from_millis(1000));
+        for (attempt, nominal) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (40, 1000),
+        ] {
+            let nominal = Duration::from_millis(nominal);
+            for _ in 0..20 {
+                let delay = policy.delay(attempt);
+                assert!(delay >= nominal / 2 && delay <= nominal, "{:?}", delay);
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{actions::StatementCounters, Error};
+use std::{fmt, sync::Arc};
+use tokio_postgres::types::Type;
+
+const DEFAULT_TABLE_NAME: &str = "casbin_rule";
+const DEFAULT_CONSTRAINT_NAME: &str = "unique_key_pg_adapter";
+
+// Postgres truncates identifiers longer than NAMEDATALEN - 1 bytes.
+const MAX_IDENTIFIER_LEN: usize = 63;
+
+pub(crate) const DEFAULT_FIELD_COUNT: usize = 6;
+
+// The unique constraint covers `ptype` plus every value column, and an index
+// holds at most INDEX_MAX_KEYS (32) columns.
+pub(crate) const MAX_FIELD_COUNT: usize = 31;
+
+/// Optionally schema-qualified name of the table holding the casbin rules.
+///
+/// Both parts are always quoted when interpolated into SQL, so names are
+/// matched case-sensitively and may contain any character except NUL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableName {
+    schema: Option<String>,
+    name: String,
+}
+
+impl TableName {
+    /// A table resolved through the connection's `search_path`.
+    pub fn new<N: Into<String>>(name: N) -> Result<Self, Error> {
+        let name = name.into();
+        validate_identifier(&name)?;
+
+        Ok(Self { schema: None, name })
+    }
+
+    /// A table inside an explicit schema. The schema must already exist.
+    pub fn with_schema<S, N>(schema: S, name: N) -> Result<Self, Error>
+    where
+        S: Into<String>,
+        N: Into<String>,
+    {
+        let schema = schema.into();
+        let name = name.into();
+        validate_identifier(&schema)?;
+        validate_identifier(&name)?;
+
+        Ok(Self {
+            schema: Some(schema),
+            name,
+        })
+    }
+
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Another table in the same schema.
+    pub(crate) fn sibling(&self, name: &str) -> TableName {
+        TableName {
+            schema: self.schema.clone(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// The table the adapter's changelog is kept in, next to the rule table.
+    pub(crate) fn changelog(&self) -> TableName {
+        self.sibling(&format!("{}_changelog", self.name))
+    }
+
+    /// Name of the unique constraint over the rule columns. Constraint
+    /// indexes share a namespace per schema, so each table gets its own.
+    pub(crate) fn unique_constraint(&self) -> String {
+        if self.name == DEFAULT_TABLE_NAME {
+            quote_identifier(DEFAULT_CONSTRAINT_NAME)
+        } else {
+            quote_identifier(&format!("{}_{}", DEFAULT_CONSTRAINT_NAME, self.name))
+        }
+    }
+}
+
+impl Default for TableName {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            name: DEFAULT_TABLE_NAME.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for TableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref schema) = self.schema {
+            write!(f, "{}.", quote_identifier(schema))?;
+        }
+        f.write_str(&quote_identifier(&self.name))
+    }
+}
+
+/// How the values of a rule are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageLayout {
+    /// One `VARCHAR` column per field (`v0`, `v1`, ...), padded with empty
+    /// strings. The number of columns is fixed when the table is created.
+    #[default]
+    Columns,
+    /// A single `rule TEXT[]` column holding as many values as the rule has.
+    TextArray,
+    /// A single `rule JSONB` column holding an array of strings.
+    Jsonb,
+}
+
+/// The rule table as seen by the statements in `actions`: its name, layout
+/// and, for [`StorageLayout::Columns`], the number of `v*` value columns,
+/// plus where mutations are announced and recorded and how its statements
+/// fare in the statement caches.
+#[derive(Debug, Clone)]
+pub(crate) struct Table {
+    pub name: TableName,
+    pub fields: usize,
+    pub layout: StorageLayout,
+    pub notify_channel: Option<String>,
+    pub changelog: Option<TableName>,
+    pub statements: Arc<StatementCounters>,
+}
+
+impl Table {
+    pub fn new(name: TableName, fields: usize, layout: StorageLayout) -> Result<Self, Error> {
+        if layout == StorageLayout::Columns && (fields == 0 || fields > MAX_FIELD_COUNT) {
+            return Err(Error::InvalidConfig(format!(
+                "field count must be between 1 and {}, got {}",
+                MAX_FIELD_COUNT, fields
+            )));
+        }
+
+        Ok(Self {
+            name,
+            fields,
+            layout,
+            notify_channel: None,
+            changelog: None,
+            statements: Arc::default(),
+        })
+    }
+
+    /// Longest rule the table can hold, `None` when the layout is unbounded.
+    pub fn max_fields(&self) -> Option<usize> {
+        match self.layout {
+            StorageLayout::Columns => Some(self.fields),
+            StorageLayout::TextArray | StorageLayout::Jsonb => None,
+        }
+    }
+
+    /// Definitions of the columns holding the rule values.
+    pub fn column_definitions(&self) -> String {
+        match self.layout {
+            StorageLayout::Columns => (0..self.fields)
+                .map(|i| format!("v{} VARCHAR NOT NULL", i))
+                .collect::<Vec<_>>()
+                .join(",\n                    "),
+            StorageLayout::TextArray => "rule TEXT[] NOT NULL".to_owned(),
+            StorageLayout::Jsonb => "rule JSONB NOT NULL".to_owned(),
+        }
+    }
+
+    /// Columns holding the rule values, in insertion order.
+    pub fn rule_columns(&self) -> String {
+        match self.layout {
+            StorageLayout::Columns => (0..self.fields)
+                .map(|i| format!("v{}", i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            StorageLayout::TextArray | StorageLayout::Jsonb => "rule".to_owned(),
+        }
+    }
+
+    /// [`Table::rule_columns`] qualified with `alias`.
+    pub fn rule_columns_of(&self, alias: &str) -> String {
+        match self.layout {
+            StorageLayout::Columns => (0..self.fields)
+                .map(|i| format!("{}.v{}", alias, i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            StorageLayout::TextArray | StorageLayout::Jsonb => format!("{}.rule", alias),
+        }
+    }
+
+    /// Value expressions matching [`Table::rule_columns`], with the rule
+    /// parameters starting at `$start`.
+    pub fn rule_values(&self, start: usize) -> String {
+        match self.layout {
+            StorageLayout::Columns => (start..start + self.fields)
+                .map(|i| format!("${}", i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            StorageLayout::TextArray => format!("${}", start),
+            StorageLayout::Jsonb => format!("to_jsonb(${}::text[])", start),
+        }
+    }
+
+    /// `SET` list overwriting the whole rule, parameters from `$start`.
+    pub fn rule_assignment(&self, start: usize) -> String {
+        format!(
+            "( {} ) = ROW( {} )",
+            self.rule_columns(),
+            self.rule_values(start)
+        )
+    }
+
+    /// Predicate matching a whole rule exactly, parameters from `$start`.
+    pub fn rule_match(&self, start: usize) -> String {
+        match self.layout {
+            StorageLayout::Columns => (0..self.fields)
+                .map(|i| format!("v{} = ${}", i, start + i))
+                .collect::<Vec<_>>()
+                .join(" AND\n                    "),
+            StorageLayout::TextArray | StorageLayout::Jsonb => {
+                format!("rule = {}", self.rule_values(start))
+            }
+        }
+    }
+
+    /// Types of `ptype` and [`Table::rule_columns`] as stored, for binary
+    /// `COPY`.
+    pub fn column_types(&self) -> Vec<Type> {
+        match self.layout {
+            StorageLayout::Columns => vec![Type::VARCHAR; self.fields + 1],
+            StorageLayout::TextArray => vec![Type::VARCHAR, Type::TEXT_ARRAY],
+            StorageLayout::Jsonb => vec![Type::VARCHAR, Type::JSONB],
+        }
+    }
+
+    /// Parameter types of `ptype` followed by the rule parameters.
+    pub fn rule_types(&self) -> Vec<Type> {
+        match self.layout {
+            StorageLayout::Columns => vec![Type::TEXT; self.fields + 1],
+            StorageLayout::TextArray | StorageLayout::Jsonb => vec![Type::TEXT, Type::TEXT_ARRAY],
+        }
+    }
+
+    /// Expression for the value at `idx`, `NULL` when the rule is shorter.
+    pub fn field(&self, idx: usize) -> String {
+        match self.layout {
+            StorageLayout::Columns => format!("v{}", idx),
+            StorageLayout::TextArray => format!("rule[{}]", idx + 1),
+            StorageLayout::Jsonb => format!("(rule ->> {})", idx),
+        }
+    }
+
+    /// Select list producing rows [`CasbinRule`](crate::models::CasbinRule)
+    /// can be built from.
+    pub fn select_columns(&self) -> String {
+        match self.layout {
+            StorageLayout::Columns => format!("id, ptype, {}", self.rule_columns()),
+            StorageLayout::TextArray => "id, ptype, rule".to_owned(),
+            StorageLayout::Jsonb => format!("id, ptype, {} AS rule", self.rule_array()),
+        }
+    }
+
+    /// The rule values as one `TEXT[]` expression over [`Table::rule_columns`].
+    pub fn rule_array(&self) -> String {
+        match self.layout {
+            StorageLayout::Columns => format!("ARRAY[{}]", self.rule_columns()),
+            StorageLayout::TextArray => "rule".to_owned(),
+            StorageLayout::Jsonb => "ARRAY(
+                    SELECT value FROM jsonb_array_elements_text(rule) WITH ORDINALITY
+                    ORDER BY ordinality
+                )"
+            .to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+pub(crate) fn validate_identifier(ident: &str) -> Result<(), Error> {
+    if ident.is_empty() {
+        return Err(Error::InvalidConfig(
+            "identifier must not be empty".to_owned(),
+        ));
+    }
+    if ident.len() > MAX_IDENTIFIER_LEN {
+        return Err(Error::InvalidConfig(format!(
+            "identifier `{}` is longer than {} bytes",
+            ident, MAX_IDENTIFIER_LEN
+        )));
+    }
+    if ident.contains('\0') {
+        return Err(Error::InvalidConfig(format!(
+            "identifier `{}` contains a NUL character",
+            ident.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn quote_identifier(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(TableName::default().to_string(), r#""casbin_rule""#);
+        assert_eq!(
+            TableName::with_schema("authz", "Rules")
+                .unwrap()
+                .to_string(),
+            r#""authz"."Rules""#
+        );
+        assert_eq!(
+            TableName::new(r#"we"ird"#).unwrap().to_string(),
+            r#""we""ird""#
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(TableName::new("").is_err());
+        assert!(TableName::new("a".repeat(64)).is_err());
+        assert!(TableName::new("a".repeat(63)).is_ok());
+        assert!(TableName::with_schema("", "casbin_rule").is_err());
+        assert!(TableName::new("nul\0byte").is_err());
+    }
+
+    #[test]
+    fn test_field_count() {
+        let table = |fields, layout| Table::new(TableName::default(), fields, layout);
+        assert!(table(0, StorageLayout::Columns).is_err());
+        assert!(table(32, StorageLayout::Columns).is_err());
+        assert!(table(0, StorageLayout::TextArray).is_ok());
+
+        let table = table(3, StorageLayout::Columns).unwrap();
+        assert_eq!(table.max_fields(), Some(3));
+        assert_eq!(table.rule_columns(), "v0, v1, v2");
+        assert_eq!(table.rule_values(2), "$2, $3, $4");
+        assert_eq!(table.rule_columns_of("t"), "t.v0, t.v1, t.v2");
+    }
+
+    #[test]
+    fn test_layouts() {
+        let table = Table::new(TableName::default(), 6, StorageLayout::TextArray).unwrap();
+        assert_eq!(table.max_fields(), None);
+        assert_eq!(table.rule_columns(), "rule");
+        assert_eq!(table.rule_match(2), "rule = $2");
+        assert_eq!(table.rule_columns_of("t"), "t.rule");
+        assert_eq!(table.field(0), "rule[1]");
+
+        let table = Table::new(TableName::default(), 6, StorageLayout::Jsonb).unwrap();
+        assert_eq!(table.rule_match(2), "rule = to_jsonb($2::text[])");
+        assert_eq!(table.field(0), "(rule ->> 0)");
+        assert_eq!(table.rule_types(), vec![Type::TEXT, Type::TEXT_ARRAY]);
+        assert_eq!(table.column_types(), vec![Type::VARCHAR, Type::JSONB]);
+    }
+
+    #[test]
+    fn test_unique_constraint() {
+        assert_eq!(
+            TableName::default().unique_constraint(),
+            r#""unique_key_pg_adapter""#
+        );
+        assert_eq!(
+            TableName::with_schema("authz", "rules")
+                .unwrap()
+                .unique_constraint(),
+            r#""unique_key_pg_adapter_rules""#
+        );
+    }
+}
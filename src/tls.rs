@@ -0,0 +1,572 @@
+#[cfg(any(feature = "tls-rustls", feature = "tls-native-tls"))]
+use std::{fmt, path::Path};
+use std::{path::PathBuf, str::FromStr};
+use tokio_postgres::config::{Config as PgConfig, SslMode as PgSslMode};
+
+use crate::error::Error;
+
+#[cfg(feature = "tls-rustls")]
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        verify_server_cert_signed_by_trust_anchor,
+    },
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    server::ParsedCertificate,
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+#[cfg(feature = "tls-rustls")]
+use std::sync::Arc;
+#[cfg(feature = "tls-rustls")]
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+#[cfg(feature = "tls-native-tls")]
+use postgres_native_tls::MakeTlsConnector;
+
+/// libpq's `sslmode`: whether to use TLS and how far to trust the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SslMode {
+    Disable,
+    /// Same as `Prefer`, tokio-postgres cannot try without TLS first.
+    Allow,
+    /// TLS if the server supports it, without checking its certificate.
+    #[default]
+    Prefer,
+    /// TLS without checking the server's certificate, unless a root
+    /// certificate is given, in which case as `VerifyCa`.
+    Require,
+    /// TLS with a server certificate issued by a trusted CA.
+    VerifyCa,
+    /// As `VerifyCa`, and the certificate must be issued for the host.
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = Error;
+
+    fn from_str(mode: &str) -> Result<Self, Error> {
+        match mode {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(Error::InvalidConfig(format!("unknown sslmode `{}`", mode))),
+        }
+    }
+}
+
+/// The CAs a verifying connector trusts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RootCerts {
+    File(PathBuf),
+    System,
+}
+
+/// TLS settings in libpq's terms, from which the `tls-rustls` and
+/// `tls-native-tls` features build connectors.
+///
+/// Verifying connectors trust the operating system's CAs unless a root
+/// certificate file is given; libpq would read `~/.postgresql/root.crt`
+/// instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    mode: SslMode,
+    root_cert: Option<RootCerts>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    pub fn new(mode: SslMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /// The settings of `url`'s `sslmode`, `sslrootcert`, `sslcert` and
+    /// `sslkey` parameters, in either url or key-value form.
+    pub fn from_url(url: &str) -> Result<Self, Error> {
+        parse_url(url).map(|(_, options)| options)
+    }
+
+    pub fn mode(&self) -> SslMode {
+        self.mode
+    }
+
+    /// Trusts only the CAs in the PEM file at `path`, like `sslrootcert`.
+    pub fn root_cert<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.root_cert = Some(RootCerts::File(path.into()));
+        self
+    }
+
+    /// Trusts the operating system's CAs, like `sslrootcert=system`.
+    pub fn system_roots(mut self) -> Self {
+        self.root_cert = Some(RootCerts::System);
+        self
+    }
+
+    /// Authenticates with the certificate chain in the PEM file `cert` and
+    /// the PKCS#8 private key in the PEM file `key`, like `sslcert` and
+    /// `sslkey`.
+    pub fn client_cert<C: Into<PathBuf>, K: Into<PathBuf>>(mut self, cert: C, key: K) -> Self {
+        self.client_cert = Some(cert.into());
+        self.client_key = Some(key.into());
+        self
+    }
+
+    /// Takes the url parameter `key` if it is a TLS setting.
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, Error> {
+        match key {
+            "sslmode" => self.mode = value.parse()?,
+            "sslrootcert" if value == "system" => self.root_cert = Some(RootCerts::System),
+            "sslrootcert" => self.root_cert = Some(RootCerts::File(value.into())),
+            "sslcert" => self.client_cert = Some(value.into()),
+            "sslkey" => self.client_key = Some(value.into()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native-tls", test))]
+    fn verifies_ca(&self) -> bool {
+        match self.mode {
+            SslMode::VerifyCa | SslMode::VerifyFull => true,
+            SslMode::Require => self.root_cert.is_some(),
+            SslMode::Disable | SslMode::Allow | SslMode::Prefer => false,
+        }
+    }
+
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native-tls"))]
+    fn client_identity(&self) -> Result<Option<(&Path, &Path)>, Error> {
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => Err(Error::InvalidConfig(
+                "a client certificate needs both sslcert and sslkey".to_owned(),
+            )),
+        }
+    }
+
+    /// A rustls connector with these settings.
+    #[cfg(feature = "tls-rustls")]
+    pub fn rustls(&self) -> Result<MakeRustlsConnect, Error> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid("TLS configuration"))?;
+        let roots = if self.verifies_ca() {
+            Some(Arc::new(self.rustls_roots()?))
+        } else {
+            None
+        };
+        let builder = match roots {
+            Some(roots) if self.mode == SslMode::VerifyFull => {
+                builder.with_root_certificates(roots)
+            }
+            roots => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(CaVerifier { roots, provider })),
+        };
+        let config = match self.client_identity()? {
+            Some((cert, key)) => {
+                let chain = CertificateDer::pem_file_iter(cert)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(invalid(cert.display()))?;
+                let key = PrivateKeyDer::from_pem_file(key).map_err(invalid(key.display()))?;
+                builder
+                    .with_client_auth_cert(chain, key)
+                    .map_err(invalid("client certificate"))?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(MakeRustlsConnect::new(config))
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    fn rustls_roots(&self) -> Result<RootCertStore, Error> {
+        let mut roots = RootCertStore::empty();
+        match &self.root_cert {
+            Some(RootCerts::File(path)) => {
+                for cert in CertificateDer::pem_file_iter(path).map_err(invalid(path.display()))? {
+                    roots
+                        .add(cert.map_err(invalid(path.display()))?)
+                        .map_err(invalid(path.display()))?;
+                }
+            }
+            Some(RootCerts::System) | None => {
+                roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+            }
+        }
+        if roots.is_empty() {
+            return Err(Error::InvalidConfig(
+                "no root certificate to verify the server with".to_owned(),
+            ));
+        }
+        Ok(roots)
+    }
+
+    /// A native-tls connector with these settings.
+    #[cfg(feature = "tls-native-tls")]
+    pub fn native_tls(&self) -> Result<MakeTlsConnector, Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if self.verifies_ca() {
+            if let Some(RootCerts::File(path)) = &self.root_cert {
+                builder.disable_built_in_roots(true);
+                let pem = std::fs::read_to_string(path).map_err(invalid(path.display()))?;
+                let certs = pem_blocks(&pem, "CERTIFICATE");
+                if certs.is_empty() {
+                    return Err(Error::InvalidConfig(format!(
+                        "`{}` holds no certificate",
+                        path.display()
+                    )));
+                }
+                for cert in certs {
+                    builder.add_root_certificate(
+                        native_tls::Certificate::from_pem(cert.as_bytes())
+                            .map_err(invalid(path.display()))?,
+                    );
+                }
+            }
+            builder.danger_accept_invalid_hostnames(self.mode != SslMode::VerifyFull);
+        } else {
+            builder.danger_accept_invalid_certs(true);
+        }
+        if let Some((cert, key)) = self.client_identity()? {
+            let cert_pem = std::fs::read(cert).map_err(invalid(cert.display()))?;
+            let key_pem = std::fs::read(key).map_err(invalid(key.display()))?;
+            builder.identity(
+                native_tls::Identity::from_pkcs8(&cert_pem, &key_pem)
+                    .map_err(invalid("client certificate"))?,
+            );
+        }
+        let connector = builder.build().map_err(invalid("TLS configuration"))?;
+        Ok(MakeTlsConnector::new(connector))
+    }
+}
+
+/// Maps an error about `what`, a file or a setting, to
+/// [`Error::InvalidConfig`].
+#[cfg(any(feature = "tls-rustls", feature = "tls-native-tls"))]
+fn invalid<W: fmt::Display, E: fmt::Display>(what: W) -> impl Fn(E) -> Error {
+    move |err| Error::InvalidConfig(format!("{}: {}", what, err))
+}
+
+/// The PEM blocks labelled `label` in `pem`, each with its delimiters.
+#[cfg(feature = "tls-native-tls")]
+fn pem_blocks<'a>(pem: &'a str, label: &str) -> Vec<&'a str> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut blocks = vec![];
+    let mut rest = pem;
+    while let Some(start) = rest.find(&begin) {
+        match rest[start..].find(&end) {
+            Some(len) => {
+                let stop = start + len + end.len();
+                blocks.push(&rest[start..stop]);
+                rest = &rest[stop..];
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+/// Checks that the server's certificate chains to `roots`, whatever host it
+/// was issued for, or accepts any certificate without `roots`. Handshake
+/// signatures are verified either way.
+#[cfg(feature = "tls-rustls")]
+#[derive(Debug)]
+struct CaVerifier {
+    roots: Option<Arc<RootCertStore>>,
+    provider: Arc<CryptoProvider>,
+}
+
+#[cfg(feature = "tls-rustls")]
+impl ServerCertVerifier for CaVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(roots) = &self.roots {
+            verify_server_cert_signed_by_trust_anchor(
+                &ParsedCertificate::try_from(end_entity)?,
+                roots,
+                intermediates,
+                now,
+                self.provider.signature_verification_algorithms.all,
+            )?;
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Parses `url` for tokio-postgres. With a TLS feature, the TLS parameters
+/// are taken out first as [`parse_url`] does; without one the url is left to
+/// tokio-postgres as it is.
+pub(crate) fn pg_config(url: &str) -> Result<PgConfig, Error> {
+    #[cfg(any(feature = "tls-rustls", feature = "tls-native-tls"))]
+    {
+        parse_url(url).map(|(config, _)| config)
+    }
+    #[cfg(not(any(feature = "tls-rustls", feature = "tls-native-tls")))]
+    {
+        PgConfig::from_str(url).map_err(Error::from)
+    }
+}
+
+/// Parses `url` for tokio-postgres after taking out the TLS parameters it
+/// does not understand, which are returned as [`TlsOptions`].
+pub(crate) fn parse_url(url: &str) -> Result<(PgConfig, TlsOptions), Error> {
+    let mut options = TlsOptions::default();
+    let url = if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        match url.split_once('?') {
+            Some((base, query)) => {
+                let mut kept = vec![];
+                for param in query.split('&') {
+                    let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                    if !options.set(key, &percent_decode(value)?)? {
+                        kept.push(param);
+                    }
+                }
+                if kept.is_empty() {
+                    base.to_owned()
+                } else {
+                    format!("{}?{}", base, kept.join("&"))
+                }
+            }
+            None => url.to_owned(),
+        }
+    } else {
+        let mut kept = vec![];
+        for (key, value) in key_values(url)? {
+            if !options.set(&key, &value)? {
+                kept.push(format!(
+                    "{}='{}'",
+                    key,
+                    value.replace('\\', "\\\\").replace('\'', "\\'")
+                ));
+            }
+        }
+        kept.join(" ")
+    };
+
+    let mut config = PgConfig::from_str(&url)?;
+    config.ssl_mode(match options.mode {
+        SslMode::Disable => PgSslMode::Disable,
+        SslMode::Allow | SslMode::Prefer => PgSslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+    });
+    Ok((config, options))
+}
+
+fn malformed() -> Error {
+    Error::InvalidConfig("malformed connection string".to_owned())
+}
+
+fn percent_decode(value: &str) -> Result<String, Error> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let byte = value
+                .get(idx + 1..idx + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(malformed)?;
+            decoded.push(byte);
+            idx += 3;
+        } else {
+            decoded.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| malformed())
+}
+
+/// The `key=value` pairs of a key-value connection string, unquoted.
+fn key_values(conninfo: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut chars = conninfo.chars().peekable();
+    let mut pairs = vec![];
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(pairs);
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && !c.is_whitespace()) {
+            key.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next() != Some('=') {
+            return Err(malformed());
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'\'').is_some() {
+            loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some('\\') => value.push(chars.next().ok_or_else(malformed)?),
+                    Some(c) => value.push(c),
+                    None => return Err(malformed()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        pairs.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pg_config() {
+        let url = "postgres://casbin_rs@localhost/casbin?sslmode=verify-full&sslrootcert=ca.pem";
+        // Only builds with a TLS feature take the TLS parameters out.
+        #[cfg(any(feature = "tls-rustls", feature = "tls-native-tls"))]
+        assert_eq!(pg_config(url).unwrap(), parse_url(url).unwrap().0);
+        #[cfg(not(any(feature = "tls-rustls", feature = "tls-native-tls")))]
+        assert!(pg_config(url).is_err());
+
+        let url = "host=localhost user=casbin_rs sslmode=require";
+        assert_eq!(pg_config(url).unwrap(), PgConfig::from_str(url).unwrap());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let (config, options) = parse_url(
+            "postgres://casbin_rs@localhost/casbin?sslmode=verify-full&application_name=casbin&sslrootcert=%2Fetc%2Fca.pem",
+        )
+        .unwrap();
+        assert_eq!(config.get_ssl_mode(), PgSslMode::Require);
+        assert_eq!(config.get_application_name(), Some("casbin"));
+        assert_eq!(
+            options,
+            TlsOptions::new(SslMode::VerifyFull).root_cert("/etc/ca.pem")
+        );
+
+        let (config, options) = parse_url(
+            "host=localhost user=casbin_rs password='it\\'s secret' sslmode = require sslrootcert=system sslcert='/tmp/my client.crt' sslkey=/tmp/client.key",
+        )
+        .unwrap();
+        assert_eq!(config.get_ssl_mode(), PgSslMode::Require);
+        assert_eq!(config.get_password(), Some(&b"it's secret"[..]));
+        assert_eq!(
+            options,
+            TlsOptions::new(SslMode::Require)
+                .system_roots()
+                .client_cert("/tmp/my client.crt", "/tmp/client.key")
+        );
+        assert!(options.verifies_ca());
+
+        let (config, options) = parse_url("postgres://localhost/casbin").unwrap();
+        assert_eq!(config.get_ssl_mode(), PgSslMode::Prefer);
+        assert_eq!(options, TlsOptions::default());
+        assert!(!options.verifies_ca());
+
+        for url in [
+            "postgres://localhost/casbin?sslmode=sometimes",
+            "postgres://localhost/casbin?sslrootcert=%2",
+            "host=localhost sslmode",
+            "host='localhost",
+            "postgres://localhost/casbin?sslcompression=1",
+        ] {
+            assert!(parse_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    #[test]
+    fn test_rustls() {
+        for options in [
+            TlsOptions::new(SslMode::Require),
+            TlsOptions::new(SslMode::VerifyCa).root_cert("examples/tls/ca.crt"),
+            TlsOptions::new(SslMode::VerifyFull)
+                .root_cert("examples/tls/ca.crt")
+                .client_cert("examples/tls/client.crt", "examples/tls/client.key"),
+        ] {
+            assert!(options.rustls().is_ok(), "{:?}", options);
+        }
+        for options in [
+            TlsOptions::new(SslMode::VerifyCa).root_cert("examples/tls/missing.crt"),
+            TlsOptions::new(SslMode::VerifyFull).root_cert("examples/tls/client.key"),
+            TlsOptions::new(SslMode::Prefer)
+                .client_cert("examples/tls/client.crt", "examples/tls/ca.crt"),
+            TlsOptions {
+                client_cert: Some("examples/tls/client.crt".into()),
+                ..TlsOptions::default()
+            },
+        ] {
+            assert!(options.rustls().is_err(), "{:?}", options);
+        }
+    }
+
+    #[cfg(feature = "tls-native-tls")]
+    #[test]
+    fn test_native_tls() {
+        for options in [
+            TlsOptions::new(SslMode::Require),
+            TlsOptions::new(SslMode::VerifyCa).root_cert("examples/tls/ca.crt"),
+            TlsOptions::new(SslMode::VerifyFull)
+                .root_cert("examples/tls/ca.crt")
+                .client_cert("examples/tls/client.crt", "examples/tls/client.key"),
+        ] {
+            assert!(options.native_tls().is_ok(), "{:?}", options);
+        }
+        for options in [
+            TlsOptions::new(SslMode::VerifyCa).root_cert("examples/tls/missing.crt"),
+            TlsOptions::new(SslMode::VerifyFull).root_cert("examples/tls/client.key"),
+            TlsOptions::new(SslMode::Prefer)
+                .client_cert("examples/tls/client.crt", "examples/tls/ca.crt"),
+        ] {
+            assert!(options.native_tls().is_err(), "{:?}", options);
+        }
+    }
+}